@@ -0,0 +1,80 @@
+use std::fmt::Debug;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+/// The source of the current time for the time-based features of the layer and the worker, such as
+/// the reset interval of the `LogSampler` or the cooldown of rate limited webhook URLs.
+///
+/// The real clock is `SystemClock`; `MockClock` allows testing these features deterministically.
+/// Delays awaited by the worker, such as the backoff between retries, use tokio's timer instead,
+/// which can be paused and advanced in tests with `tokio::time::pause`.
+pub trait Clock: Debug + Send + Sync {
+    /// The current monotonic time.
+    fn now(&self) -> Instant;
+
+    /// The current wall-clock time.
+    fn system_time(&self) -> SystemTime;
+}
+
+/// The real clock of the system.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A clock standing still until it is explicitly advanced, for testing.
+#[derive(Debug)]
+pub struct MockClock {
+    start: Instant,
+    start_system_time: SystemTime,
+    elapsed: Mutex<Duration>,
+}
+
+impl MockClock {
+    /// Create a clock stopped at the current time.
+    pub fn new() -> Self {
+        Self::at(SystemTime::now())
+    }
+
+    /// Create a clock stopped at the given wall-clock time.
+    pub fn at(system_time: SystemTime) -> Self {
+        Self {
+            start: Instant::now(),
+            start_system_time: system_time,
+            elapsed: Mutex::new(Duration::ZERO),
+        }
+    }
+
+    /// Move the clock forward.
+    pub fn advance(&self, duration: Duration) {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner()) += duration;
+    }
+
+    fn elapsed(&self) -> Duration {
+        *self.elapsed.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        self.start + self.elapsed()
+    }
+
+    fn system_time(&self) -> SystemTime {
+        self.start_system_time + self.elapsed()
+    }
+}
@@ -1,9 +1,31 @@
-use regex::Regex;
+use std::{
+    collections::HashMap,
+    fmt,
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use regex::{Regex, RegexBuilder};
+use tracing::log::LevelFilter;
+use tracing::Level;
+
+use crate::clock::{Clock, SystemClock};
+use crate::state::StateStore;
 
 pub trait Filter {
     fn process(&self, value: &str) -> Result<(), FilterError>;
 }
 
+/// The 64-bit FNV-1a hash of the value, which the filters key their state by. Unlike the hashers of
+/// the standard library it is the same in every process and build, so that the keys of a shared
+/// state store agree across the instances of a service.
+fn stable_hash(value: &str) -> u64 {
+    value.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
 /// EventFilters describes two optional lists of regular expressions used to filter events.
 ///
 /// If provided, each expression is used in either negatively ("does NOT MATCH") or
@@ -21,6 +43,167 @@ impl EventFilters {
     pub fn new(positive: Option<Vec<Regex>>, negative: Option<Vec<Regex>>) -> Self {
         Self { positive, negative }
     }
+
+    /// Match every expression regardless of case, as if each started with `(?i)`.
+    pub fn case_insensitive(self) -> Self {
+        Self {
+            positive: self.positive.map(case_insensitive),
+            negative: self.negative.map(case_insensitive),
+        }
+    }
+
+    /// Filter a missing value, e.g. the name of the span of an event outside any span, which fails
+    /// every positive filter and passes every negative one.
+    pub(crate) fn process_missing(&self) -> Result<(), FilterError> {
+        match &self.positive {
+            Some(positive) if !positive.is_empty() => Err(FilterError::PositiveFilterFailed),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether there are no expressions to filter with, i.e. every value passes.
+    pub fn is_empty(&self) -> bool {
+        self.positive.is_none() && self.negative.is_none()
+    }
+
+    /// Read a pair of positive and negative regular expressions from the environment.
+    ///
+    /// Returns `None` if neither variable is set.
+    fn from_env(positive_var: &'static str, negative_var: &'static str) -> Result<Option<Self>, EnvFilterError> {
+        let positive = regex_from_env(positive_var)?;
+        let negative = regex_from_env(negative_var)?;
+        if positive.is_none() && negative.is_none() {
+            return Ok(None);
+        }
+        Ok(Some(Self::from((positive, negative))))
+    }
+}
+
+/// The given regular expressions, recompiled to match regardless of case.
+pub(crate) fn case_insensitive(regexes: Vec<Regex>) -> Vec<Regex> {
+    regexes
+        .into_iter()
+        .map(|regex| {
+            // The expression already compiled, so it only fails if the case-insensitive version is too
+            // large, in which case it is kept as is.
+            RegexBuilder::new(regex.as_str()).case_insensitive(true).build().unwrap_or(regex)
+        })
+        .collect()
+}
+
+fn regex_from_env(var: &'static str) -> Result<Option<Regex>, EnvFilterError> {
+    match std::env::var(var) {
+        Ok(value) => Regex::new(&value)
+            .map(Some)
+            .map_err(|source| EnvFilterError::InvalidRegex { var, source }),
+        Err(_) => Ok(None),
+    }
+}
+
+/// Read a comma-separated list of module prefixes from the environment, as a single regular
+/// expression matching the targets in any of these modules, e.g. `app::db,hyper` matches
+/// `app::db`, `app::db::pool`, and `hyper::client`, but not `app::dbx`.
+fn targets_from_env(var: &'static str) -> Result<Option<Regex>, EnvFilterError> {
+    let value = match std::env::var(var) {
+        Ok(value) => value,
+        Err(_) => return Ok(None),
+    };
+    let mut prefixes = Vec::new();
+    for prefix in value.split(',').map(str::trim) {
+        let is_module_path = !prefix.is_empty()
+            && prefix.split("::").all(|segment| {
+                !segment.is_empty() && segment.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-')
+            });
+        if !is_module_path {
+            return Err(EnvFilterError::InvalidTarget {
+                var,
+                value: prefix.to_string(),
+            });
+        }
+        prefixes.push(regex::escape(prefix));
+    }
+    let pattern = format!("^(?:{})(?:::|$)", prefixes.join("|"));
+    Regex::new(&pattern)
+        .map(Some)
+        .map_err(|source| EnvFilterError::InvalidRegex { var, source })
+}
+
+/// Filters read from the environment, used to tune which events are sent without recompiling.
+///
+/// See `WebhookLayerBuilder::filters_from_env` for the supported variables.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct EnvFilters {
+    pub(crate) target_filters: Option<EventFilters>,
+    pub(crate) message_filters: Option<EventFilters>,
+    pub(crate) event_by_field_filters: Option<EventFilters>,
+    pub(crate) level_filter: Option<String>,
+}
+
+impl EnvFilters {
+    pub(crate) const TARGETS: &'static str = "WEBHOOK_TARGETS";
+    pub(crate) const TARGET_INCLUDE: &'static str = "WEBHOOK_TARGET_INCLUDE";
+    pub(crate) const TARGET_EXCLUDE: &'static str = "WEBHOOK_TARGET_EXCLUDE";
+    pub(crate) const MESSAGE_INCLUDE: &'static str = "WEBHOOK_MESSAGE_INCLUDE";
+    pub(crate) const MESSAGE_EXCLUDE: &'static str = "WEBHOOK_MESSAGE_EXCLUDE";
+    pub(crate) const FIELD_INCLUDE: &'static str = "WEBHOOK_FIELD_INCLUDE";
+    pub(crate) const FIELD_EXCLUDE: &'static str = "WEBHOOK_FIELD_EXCLUDE";
+    pub(crate) const LEVEL: &'static str = "WEBHOOK_LEVEL";
+
+    pub(crate) fn from_env() -> Result<Self, EnvFilterError> {
+        let level_filter = match std::env::var(Self::LEVEL) {
+            Ok(level) => {
+                LevelDirectives::from_str(&level).map_err(|_| EnvFilterError::InvalidLevel {
+                    var: Self::LEVEL,
+                    value: level.clone(),
+                })?;
+                Some(level)
+            }
+            Err(_) => None,
+        };
+        let mut target_filters = EventFilters::from_env(Self::TARGET_INCLUDE, Self::TARGET_EXCLUDE)?;
+        if let Some(targets) = targets_from_env(Self::TARGETS)? {
+            let filters = target_filters.get_or_insert_with(EventFilters::default);
+            filters.positive.get_or_insert_with(Vec::new).push(targets);
+        }
+        Ok(Self {
+            target_filters,
+            message_filters: EventFilters::from_env(Self::MESSAGE_INCLUDE, Self::MESSAGE_EXCLUDE)?,
+            event_by_field_filters: EventFilters::from_env(Self::FIELD_INCLUDE, Self::FIELD_EXCLUDE)?,
+            level_filter,
+        })
+    }
+}
+
+/// An error reading filters from the environment.
+#[derive(Debug)]
+pub enum EnvFilterError {
+    /// The variable does not hold a valid regular expression.
+    InvalidRegex { var: &'static str, source: regex::Error },
+    /// The variable does not hold a valid level, e.g. `warn`.
+    InvalidLevel { var: &'static str, value: String },
+    /// An entry of the variable's comma-separated list is not a valid module path, e.g. `app::db`.
+    InvalidTarget { var: &'static str, value: String },
+}
+
+impl fmt::Display for EnvFilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EnvFilterError::InvalidRegex { var, source } => {
+                write!(f, "invalid regular expression in {}: {}", var, source)
+            }
+            EnvFilterError::InvalidLevel { var, value } => write!(f, "invalid level in {}: {:?}", var, value),
+            EnvFilterError::InvalidTarget { var, value } => write!(f, "invalid module path in {}: {:?}", var, value),
+        }
+    }
+}
+
+impl std::error::Error for EnvFilterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            EnvFilterError::InvalidRegex { source, .. } => Some(source),
+            EnvFilterError::InvalidLevel { .. } | EnvFilterError::InvalidTarget { .. } => None,
+        }
+    }
 }
 
 impl Filter for EventFilters {
@@ -102,21 +285,907 @@ impl Filter for Option<Vec<Regex>> {
     }
 }
 
+/// Forwards only the 1st, `base`th, `base^2`th, ... occurrence of each distinct value, so the onset
+/// and escalation of a repetitive event are visible without a flood of messages.
+///
+/// Occurrences are counted per value in memory. To keep memory bounded, the counts are reset after
+/// `reset_interval`, or once more than `max_keys` distinct values have been seen.
+#[derive(Debug)]
+pub struct LogSampler {
+    base: u64,
+    reset_interval: Duration,
+    max_keys: usize,
+    clock: Arc<dyn Clock>,
+    state: Mutex<LogSamplerState>,
+}
+
+#[derive(Debug)]
+struct LogSamplerState {
+    counts: HashMap<u64, u64>,
+    /// When the counts were last reset, or `None` before the first value is counted.
+    last_reset: Option<Instant>,
+}
+
+impl LogSampler {
+    /// Create a sampler forwarding occurrences numbered by the powers of `base`, which must be at
+    /// least 2. Counts are reset every hour, or after 1024 distinct values.
+    pub fn new(base: u64) -> Self {
+        assert!(base >= 2, "log sampler base must be at least 2");
+        Self {
+            base,
+            reset_interval: Duration::from_secs(60 * 60),
+            max_keys: 1024,
+            clock: Arc::new(SystemClock),
+            state: Mutex::new(LogSamplerState {
+                counts: HashMap::new(),
+                last_reset: None,
+            }),
+        }
+    }
+
+    /// How often to forget all occurrence counts.
+    pub fn reset_interval(mut self, reset_interval: Duration) -> Self {
+        self.reset_interval = reset_interval;
+        self
+    }
+
+    /// The number of distinct values to count before forgetting all occurrence counts.
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    /// Use the clock of the layer's config to measure the reset interval.
+    pub(crate) fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn is_power_of_base(&self, mut count: u64) -> bool {
+        while count.is_multiple_of(self.base) {
+            count /= self.base;
+        }
+        count == 1
+    }
+}
+
+impl Default for LogSampler {
+    fn default() -> Self {
+        Self::new(10)
+    }
+}
+
+impl Filter for LogSampler {
+    fn process(&self, value: &str) -> Result<(), FilterError> {
+        let key = stable_hash(value);
+
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let last_reset = *state.last_reset.get_or_insert(now);
+        if now.duration_since(last_reset) >= self.reset_interval
+            || (state.counts.len() >= self.max_keys && !state.counts.contains_key(&key))
+        {
+            state.counts.clear();
+            state.last_reset = Some(now);
+        }
+        let count = state.counts.entry(key).or_insert(0);
+        *count += 1;
+        if self.is_power_of_base(*count) {
+            Ok(())
+        } else {
+            Err(FilterError::Sampled)
+        }
+    }
+}
+
+impl Filter for Option<LogSampler> {
+    fn process(&self, value: &str) -> Result<(), FilterError> {
+        if let Some(sampler) = self {
+            sampler.process(value)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Forwards at most `max_events` occurrences of each distinct value per `window`, dropping the
+/// rest, so a code path emitting thousands of identical events during an incident does not turn
+/// each of them into a webhook call.
+///
+/// Each value has a token bucket holding up to `max_events` tokens, refilled at a rate of
+/// `max_events` per `window`. Buckets are kept in memory, and forgotten once more than `max_keys`
+/// distinct values have been seen.
+#[derive(Debug)]
+pub struct RateLimitFilter {
+    max_events: u32,
+    window: Duration,
+    max_keys: usize,
+    summarize_suppressed: bool,
+    clock: Arc<dyn Clock>,
+    buckets: Mutex<HashMap<u64, TokenBucket>>,
+    store: Option<Arc<dyn StateStore>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    tokens: f64,
+    last_refill: Instant,
+    /// The number of occurrences dropped since the last one forwarded.
+    suppressed: u64,
+}
+
+impl RateLimitFilter {
+    /// Create a filter forwarding at most `max_events` occurrences of each value per `window`. Up to
+    /// 1024 distinct values are tracked.
+    pub fn new(max_events: u32, window: Duration) -> Self {
+        Self {
+            max_events: max_events.max(1),
+            window,
+            max_keys: 1024,
+            summarize_suppressed: false,
+            clock: Arc::new(SystemClock),
+            buckets: Mutex::new(HashMap::new()),
+            store: None,
+        }
+    }
+
+    /// The number of distinct values to track before forgetting every bucket.
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    /// Append the number of occurrences dropped since the previous one forwarded to the message of
+    /// the next one forwarded, e.g. `(suppressed 42 similar events)`.
+    pub fn summarize_suppressed(mut self) -> Self {
+        self.summarize_suppressed = true;
+        self
+    }
+
+    /// Keep the buckets in a state store rather than in memory, e.g. one shared by several instances
+    /// of a service so that they are limited together. The store bounds the number of buckets
+    /// instead of `max_keys`.
+    pub fn state_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Whether to report the number of occurrences dropped with `check`.
+    pub(crate) fn summarizes_suppressed(&self) -> bool {
+        self.summarize_suppressed
+    }
+
+    /// Use the clock of the layer's config to refill the buckets.
+    pub(crate) fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Take a token from the bucket of the value, returning the number of occurrences dropped since
+    /// the previous one forwarded, or an error if the bucket is empty.
+    pub(crate) fn check(&self, value: &str) -> Result<u64, FilterError> {
+        let key = stable_hash(value);
+        if let Some(store) = &self.store {
+            return self.check_in_store(store.as_ref(), key);
+        }
+
+        let now = self.clock.now();
+        let mut buckets = self.buckets.lock().unwrap_or_else(|e| e.into_inner());
+        if buckets.len() >= self.max_keys && !buckets.contains_key(&key) {
+            buckets.clear();
+        }
+        let capacity = f64::from(self.max_events);
+        let bucket = buckets.entry(key).or_insert(TokenBucket {
+            tokens: capacity,
+            last_refill: now,
+            suppressed: 0,
+        });
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = self.refill(bucket.tokens, elapsed);
+        bucket.last_refill = now;
+        if bucket.tokens < 1.0 {
+            bucket.suppressed += 1;
+            return Err(FilterError::RateLimited);
+        }
+        bucket.tokens -= 1.0;
+        Ok(std::mem::take(&mut bucket.suppressed))
+    }
+
+    /// Like `check`, with the bucket of the value kept in the state store as `tokens last_refill
+    /// suppressed`, the time of the last refill in milliseconds since the Unix epoch.
+    fn check_in_store(&self, store: &dyn StateStore, key: u64) -> Result<u64, FilterError> {
+        let key = format!("rate-limit:{:x}", key);
+        let now = unix_millis(self.clock.system_time());
+        let bucket = store.get(&key).unwrap_or_default();
+        let mut bucket = bucket.split(' ');
+        let tokens = bucket.next().and_then(|tokens| tokens.parse().ok());
+        let last_refill = bucket.next().and_then(|last_refill| last_refill.parse().ok()).unwrap_or(now);
+        let suppressed: u64 = bucket.next().and_then(|suppressed| suppressed.parse().ok()).unwrap_or(0);
+        let elapsed = Duration::from_millis(now.saturating_sub(last_refill));
+        let tokens = self.refill(tokens.unwrap_or(f64::from(self.max_events)), elapsed);
+        if tokens < 1.0 {
+            store.set(&key, format!("{} {} {}", tokens, now, suppressed + 1));
+            return Err(FilterError::RateLimited);
+        }
+        store.set(&key, format!("{} {} 0", tokens - 1.0, now));
+        Ok(suppressed)
+    }
+
+    /// The tokens of a bucket holding `tokens` after `elapsed` of refilling.
+    fn refill(&self, tokens: f64, elapsed: Duration) -> f64 {
+        let capacity = f64::from(self.max_events);
+        let refill = if self.window.is_zero() {
+            capacity
+        } else {
+            elapsed.as_secs_f64() / self.window.as_secs_f64() * capacity
+        };
+        (tokens + refill).min(capacity)
+    }
+}
+
+impl Filter for RateLimitFilter {
+    fn process(&self, value: &str) -> Result<(), FilterError> {
+        self.check(value).map(|_| ())
+    }
+}
+
+/// Drops exact duplicates of a value forwarded less than `window` ago, so a flapping condition does
+/// not bury the channel under copies of the same alert. A value repeated continuously is still
+/// forwarded once per `window`.
+///
+/// The values forwarded are remembered in memory, up to `max_keys` of them.
+#[derive(Debug)]
+pub struct DedupFilter {
+    window: Duration,
+    max_keys: usize,
+    count_suppressed: bool,
+    clock: Arc<dyn Clock>,
+    state: Mutex<DedupState>,
+    store: Option<Arc<dyn StateStore>>,
+}
+
+#[derive(Debug, Default)]
+struct DedupState {
+    /// When each value was last forwarded.
+    forwarded: HashMap<u64, Instant>,
+    /// The number of duplicates dropped since a value was last forwarded.
+    suppressed: u64,
+}
+
+impl DedupFilter {
+    /// Create a filter dropping duplicates forwarded less than `window` ago. Up to 1024 distinct
+    /// values are remembered.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            max_keys: 1024,
+            count_suppressed: false,
+            clock: Arc::new(SystemClock),
+            state: Mutex::default(),
+            store: None,
+        }
+    }
+
+    /// The number of distinct values to remember. Once reached, the values forwarded more than
+    /// `window` ago are forgotten, or every value if none is.
+    pub fn max_keys(mut self, max_keys: usize) -> Self {
+        self.max_keys = max_keys;
+        self
+    }
+
+    /// Append the number of duplicates dropped to the message of the next value forwarded, e.g.
+    /// `(suppressed 3 duplicate events)`.
+    pub fn count_suppressed(mut self) -> Self {
+        self.count_suppressed = true;
+        self
+    }
+
+    /// Remember the values forwarded in a state store rather than in memory, e.g. one shared by
+    /// several instances of a service so that a duplicate is dropped whichever instance forwarded
+    /// the original. The store bounds the number of values remembered instead of `max_keys`.
+    pub fn state_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Whether to report the number of duplicates dropped with `check`.
+    pub(crate) fn counts_suppressed(&self) -> bool {
+        self.count_suppressed
+    }
+
+    /// Use the clock of the layer's config to measure the window.
+    pub(crate) fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Check whether the value is a duplicate, returning the number of duplicates dropped since a
+    /// value was last forwarded, or an error if it is a duplicate itself.
+    pub(crate) fn check(&self, value: &str) -> Result<u64, FilterError> {
+        let key = stable_hash(value);
+        if let Some(store) = &self.store {
+            return self.check_in_store(store.as_ref(), key);
+        }
+
+        let now = self.clock.now();
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(forwarded) = state.forwarded.get(&key) {
+            if now.saturating_duration_since(*forwarded) < self.window {
+                state.suppressed += 1;
+                return Err(FilterError::Duplicate);
+            }
+        }
+        if state.forwarded.len() >= self.max_keys && !state.forwarded.contains_key(&key) {
+            let window = self.window;
+            state.forwarded.retain(|_, forwarded| now.saturating_duration_since(*forwarded) < window);
+            if state.forwarded.len() >= self.max_keys {
+                state.forwarded.clear();
+            }
+        }
+        state.forwarded.insert(key, now);
+        Ok(std::mem::take(&mut state.suppressed))
+    }
+
+    /// Like `check`, with when each value was last forwarded kept in the state store, in
+    /// milliseconds since the Unix epoch.
+    fn check_in_store(&self, store: &dyn StateStore, key: u64) -> Result<u64, FilterError> {
+        let key = format!("dedup:{:x}", key);
+        let now = unix_millis(self.clock.system_time());
+        if let Some(forwarded) = store.get(&key).and_then(|forwarded| forwarded.parse::<u64>().ok()) {
+            if Duration::from_millis(now.saturating_sub(forwarded)) < self.window {
+                store.incr(DEDUP_SUPPRESSED_KEY, 1);
+                return Err(FilterError::Duplicate);
+            }
+        }
+        store.set(&key, now.to_string());
+        let suppressed = store.incr(DEDUP_SUPPRESSED_KEY, 0);
+        store.incr(DEDUP_SUPPRESSED_KEY, -suppressed);
+        Ok(suppressed.max(0) as u64)
+    }
+}
+
+/// The key of the number of duplicates dropped since a value was last forwarded, in a state store.
+const DEDUP_SUPPRESSED_KEY: &str = "dedup:suppressed";
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+impl Filter for DedupFilter {
+    fn process(&self, value: &str) -> Result<(), FilterError> {
+        self.check(value).map(|_| ())
+    }
+}
+
+/// Level thresholds per target, parsed from directives like those of `RUST_LOG`, e.g.
+/// `warn,myapp::db=info,myapp::http=error`.
+///
+/// A directive without a target, e.g. `warn`, sets the threshold of events matching no other
+/// directive. The directive with the longest target that is a prefix of the event's target applies.
+/// As with `EnvFilter`, events matching no directive are not sent if there is no such default.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct LevelDirectives {
+    default: Option<LevelFilter>,
+    targets: Vec<(String, LevelFilter)>,
+}
+
+impl LevelDirectives {
+    /// Whether an event of the given level and target is at or above its threshold.
+    pub(crate) fn enabled(&self, level: &Level, target: &str) -> bool {
+        let threshold = self
+            .targets
+            .iter()
+            .filter(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .max_by_key(|(prefix, _)| prefix.len())
+            .map(|(_, threshold)| *threshold)
+            .or(self.default)
+            .unwrap_or(LevelFilter::Off);
+        let level = match *level {
+            Level::ERROR => LevelFilter::Error,
+            Level::WARN => LevelFilter::Warn,
+            Level::INFO => LevelFilter::Info,
+            Level::DEBUG => LevelFilter::Debug,
+            Level::TRACE => LevelFilter::Trace,
+        };
+        level <= threshold
+    }
+}
+
+impl FromStr for LevelDirectives {
+    type Err = tracing::log::ParseLevelError;
+
+    fn from_str(directives: &str) -> Result<Self, Self::Err> {
+        let mut default = None;
+        let mut targets = Vec::new();
+        for directive in directives.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    targets.push((target.trim().to_string(), LevelFilter::from_str(level.trim())?));
+                }
+                None => default = Some(LevelFilter::from_str(directive)?),
+            }
+        }
+        if default.is_none() && targets.is_empty() {
+            // Reject an empty string like a bare level would be.
+            LevelFilter::from_str(directives)?;
+        }
+        Ok(Self { default, targets })
+    }
+}
+
+/// A band of levels, from the most severe `min` to the most verbose `max`, both included, e.g. WARN
+/// and ERROR with `LevelRange::new(Level::ERROR, Level::WARN)`.
+///
+/// Levels compare as more verbose being greater, so ERROR is the lowest level and TRACE the highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelRange {
+    pub min: Level,
+    pub max: Level,
+}
+
+impl LevelRange {
+    pub fn new(min: Level, max: Level) -> Self {
+        Self { min, max }
+    }
+
+    /// Every level at or above the severity of `max`, as selected by a single threshold.
+    pub fn at_most(max: Level) -> Self {
+        Self::new(Level::ERROR, max)
+    }
+
+    /// Whether the level is within the range.
+    pub fn contains(&self, level: &Level) -> bool {
+        self.min <= *level && *level <= self.max
+    }
+}
+
+/// A comparison operator of a `FieldThreshold`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThresholdOp {
+    /// The field is greater than the threshold.
+    Gt,
+    /// The field is greater than or equal to the threshold.
+    Gte,
+    /// The field is less than the threshold.
+    Lt,
+    /// The field is less than or equal to the threshold.
+    Lte,
+    /// The field is equal to the threshold.
+    Eq,
+}
+
+/// A condition on the value of a field of the span of an event, e.g. `tenant_id` matching `^vip$`,
+/// used to only send the events of some tenants or requests.
+///
+/// The field is looked up in the span of the event, then in its ancestors, the nearest span recording
+/// it taking precedence. Values other than strings are matched in their JSON form, e.g. `42` or
+/// `true`. An event whose span does not record the field fails the condition, unless
+/// `pass_if_missing` is set.
+#[derive(Debug, Clone)]
+pub struct SpanFieldFilter {
+    pub key: String,
+    pub value: Regex,
+    pub pass_if_missing: bool,
+}
+
+impl SpanFieldFilter {
+    pub fn new(key: impl Into<String>, value: Regex) -> Self {
+        Self {
+            key: key.into(),
+            value,
+            pass_if_missing: false,
+        }
+    }
+
+    /// Pass the condition when the field is missing, instead of failing it.
+    pub fn pass_if_missing(mut self) -> Self {
+        self.pass_if_missing = true;
+        self
+    }
+
+    /// Whether the value of the field, if recorded, meets the condition.
+    pub(crate) fn matches(&self, field: Option<&serde_json::Value>) -> bool {
+        match field {
+            Some(serde_json::Value::String(s)) => self.value.is_match(s),
+            Some(field) => self.value.is_match(&field.to_string()),
+            None => self.pass_if_missing,
+        }
+    }
+}
+
+/// A condition on a numeric field of an event, e.g. `latency_ms > 1000`, used to only send events
+/// crossing a threshold.
+///
+/// An event whose field is missing or not a number fails the condition, unless `pass_if_missing` is
+/// set.
+#[derive(Debug, Clone)]
+pub struct FieldThreshold {
+    pub key: String,
+    pub op: ThresholdOp,
+    pub value: f64,
+    pub pass_if_missing: bool,
+}
+
+impl FieldThreshold {
+    pub fn new(key: impl Into<String>, op: ThresholdOp, value: f64) -> Self {
+        Self {
+            key: key.into(),
+            op,
+            value,
+            pass_if_missing: false,
+        }
+    }
+
+    /// Pass the condition when the field is missing or not a number, instead of failing it.
+    pub fn pass_if_missing(mut self) -> Self {
+        self.pass_if_missing = true;
+        self
+    }
+
+    /// Whether the value of the field, if recorded, meets the condition.
+    pub(crate) fn matches(&self, field: Option<&serde_json::Value>) -> bool {
+        let field = match field.and_then(serde_json::Value::as_f64) {
+            Some(field) => field,
+            None => return self.pass_if_missing,
+        };
+        match self.op {
+            ThresholdOp::Gt => field > self.value,
+            ThresholdOp::Gte => field >= self.value,
+            ThresholdOp::Lt => field < self.value,
+            ThresholdOp::Lte => field <= self.value,
+            ThresholdOp::Eq => field == self.value,
+        }
+    }
+}
+
+/// The reason an event or span was not sent to the webhook.
+#[derive(Debug)]
 pub enum FilterError {
+    /// A positive filter did not match, e.g. the target of the event.
     PositiveFilterFailed,
+    /// A negative filter matched.
     NegativeMatchFailed,
-    IoError(Box<dyn std::error::Error>),
-    SerdeError(serde_json::Error),
+    /// The `LogSampler` dropped the event.
+    Sampled,
+    /// The `RateLimitFilter` dropped the event.
+    RateLimited,
+    /// The `DedupFilter` dropped the event as a duplicate of a recent one.
+    Duplicate,
+    /// The message factory panicked.
+    Panicked,
+    /// The fields of the event could not be serialized.
+    SerializationFailed(serde_json::Error),
 }
 
-impl From<Box<dyn std::error::Error>> for FilterError {
-    fn from(e: Box<dyn std::error::Error>) -> Self {
-        FilterError::IoError(e)
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::PositiveFilterFailed => f.write_str("no positive filter matched"),
+            FilterError::NegativeMatchFailed => f.write_str("a negative filter matched"),
+            FilterError::Sampled => f.write_str("dropped by the log sampler"),
+            FilterError::RateLimited => f.write_str("dropped by the rate limit"),
+            FilterError::Duplicate => f.write_str("dropped as a duplicate"),
+            FilterError::Panicked => f.write_str("the webhook message factory panicked"),
+            FilterError::SerializationFailed(e) => write!(f, "failed to serialize the fields: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for FilterError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            FilterError::SerializationFailed(e) => Some(e),
+            _ => None,
+        }
     }
 }
 
 impl From<serde_json::Error> for FilterError {
     fn from(e: serde_json::Error) -> Self {
-        FilterError::SerdeError(e)
+        FilterError::SerializationFailed(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    use serde_json::json;
+
+    use regex::Regex;
+
+    use tracing::Level;
+
+    use super::{
+        stable_hash, DedupFilter, EnvFilterError, EnvFilters, EventFilters, FieldThreshold, Filter, FilterError,
+        LevelDirectives, LevelRange, LogSampler, RateLimitFilter, SpanFieldFilter, ThresholdOp,
+    };
+    use crate::clock::MockClock;
+    use crate::layer::source_location;
+    use crate::state::{MemoryStateStore, StateStore};
+
+    #[test]
+    fn field_threshold_compares_integer_fields() {
+        let threshold = FieldThreshold::new("retry_count", ThresholdOp::Gte, 5.0);
+        assert!(threshold.matches(Some(&json!(5))));
+        assert!(threshold.matches(Some(&json!(7u64))));
+        assert!(!threshold.matches(Some(&json!(4))));
+        assert!(FieldThreshold::new("retry_count", ThresholdOp::Eq, 5.0).matches(Some(&json!(5))));
+        assert!(FieldThreshold::new("retry_count", ThresholdOp::Lt, 0.0).matches(Some(&json!(-1))));
+    }
+
+    #[test]
+    fn field_threshold_compares_float_fields() {
+        let threshold = FieldThreshold::new("latency_ms", ThresholdOp::Gt, 1000.0);
+        assert!(threshold.matches(Some(&json!(1000.5))));
+        assert!(!threshold.matches(Some(&json!(1000.0))));
+        assert!(FieldThreshold::new("latency_ms", ThresholdOp::Lte, 0.25).matches(Some(&json!(0.25))));
+    }
+
+    #[test]
+    fn field_threshold_fails_missing_or_non_numeric_fields_unless_configured() {
+        let threshold = FieldThreshold::new("latency_ms", ThresholdOp::Gt, 1000.0);
+        assert!(!threshold.matches(None));
+        assert!(!threshold.matches(Some(&json!("2000"))));
+        let threshold = threshold.pass_if_missing();
+        assert!(threshold.matches(None));
+        assert!(threshold.matches(Some(&json!("2000"))));
+    }
+
+    #[test]
+    fn case_insensitive_filters_ignore_case() {
+        let filters = EventFilters::from((Regex::new("timeout").unwrap(), Regex::new("^Retry").unwrap()));
+        assert!(filters.process("Connection TIMEOUT").is_err());
+        let filters = filters.case_insensitive();
+        assert!(filters.process("Connection TIMEOUT").is_ok());
+        assert!(filters.process("retry: connection timeout").is_err());
+    }
+
+    #[test]
+    fn rate_limit_drops_events_beyond_the_limit_until_refilled() {
+        let clock = Arc::new(MockClock::new());
+        let rate_limit = RateLimitFilter::new(2, Duration::from_secs(60)).with_clock(clock.clone());
+        assert_eq!(rate_limit.check("app\ntimeout").ok(), Some(0));
+        assert_eq!(rate_limit.check("app\ntimeout").ok(), Some(0));
+        assert!(rate_limit.check("app\ntimeout").is_err());
+        assert!(rate_limit.check("app\ntimeout").is_err());
+        // Other events have their own bucket.
+        assert!(rate_limit.check("app\nconnected").is_ok());
+
+        // Half the window refills a single token, and reports the events dropped meanwhile.
+        clock.advance(Duration::from_secs(30));
+        assert_eq!(rate_limit.check("app\ntimeout").ok(), Some(2));
+        assert!(rate_limit.check("app\ntimeout").is_err());
+    }
+
+    #[test]
+    fn log_sampler_forwards_the_occurrences_numbered_by_powers_of_its_base() {
+        let clock = Arc::new(MockClock::new());
+        let sampler = LogSampler::new(10).max_keys(2).with_clock(clock.clone());
+        let forwarded: Vec<_> = (1..=250).filter(|_| sampler.process("timeout").is_ok()).collect();
+        assert_eq!(forwarded, [1, 10, 100]);
+        // Other values are counted separately.
+        assert!(sampler.process("refused").is_ok());
+        assert!(sampler.process("refused").is_err());
+
+        // Counts are forgotten past the reset interval.
+        clock.advance(Duration::from_secs(60 * 60));
+        assert!(sampler.process("timeout").is_ok());
+        assert!(sampler.process("timeout").is_err());
+        assert!(sampler.process("refused").is_ok());
+        // And beyond the maximum number of values, or this would be the 3rd occurrence of `timeout`.
+        assert!(sampler.process("closed").is_ok());
+        assert!(sampler.process("timeout").is_ok());
+    }
+
+    #[test]
+    fn dedup_drops_duplicates_within_the_window() {
+        let clock = Arc::new(MockClock::new());
+        let dedup = DedupFilter::new(Duration::from_secs(60)).with_clock(clock.clone());
+        assert_eq!(dedup.check("app\ntimeout\n{\"host\":\"a\"}").ok(), Some(0));
+        assert!(dedup.check("app\ntimeout\n{\"host\":\"a\"}").is_err());
+        assert!(dedup.check("app\ntimeout\n{\"host\":\"a\"}").is_err());
+        // Events with other fields are not duplicates, and report the duplicates dropped meanwhile.
+        assert_eq!(dedup.check("app\ntimeout\n{\"host\":\"b\"}").ok(), Some(2));
+
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(dedup.check("app\ntimeout\n{\"host\":\"a\"}").ok(), Some(0));
+    }
+
+    #[test]
+    fn filters_sharing_a_state_store_share_their_state() {
+        let clock = Arc::new(MockClock::new());
+        let store = Arc::new(MemoryStateStore::default());
+        let dedup = |store: Arc<MemoryStateStore>| {
+            DedupFilter::new(Duration::from_secs(60)).state_store(store).with_clock(clock.clone())
+        };
+        let (first, second) = (dedup(store.clone()), dedup(store.clone()));
+        assert_eq!(first.check("app\ntimeout").ok(), Some(0));
+        assert!(second.check("app\ntimeout").is_err());
+        assert_eq!(first.check("app\nconnected").ok(), Some(1));
+        clock.advance(Duration::from_secs(60));
+        assert!(second.check("app\ntimeout").is_ok());
+
+        let rate_limit = |store: Arc<MemoryStateStore>| {
+            RateLimitFilter::new(1, Duration::from_secs(60)).state_store(store).with_clock(clock.clone())
+        };
+        let (first, second) = (rate_limit(store.clone()), rate_limit(store));
+        assert_eq!(first.check("app\ntimeout").ok(), Some(0));
+        assert!(second.check("app\ntimeout").is_err());
+        clock.advance(Duration::from_secs(60));
+        assert_eq!(first.check("app\ntimeout").ok(), Some(1));
+    }
+
+    #[test]
+    fn filters_key_a_state_store_by_a_hash_stable_across_processes() {
+        assert_eq!(stable_hash(""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(stable_hash("a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(stable_hash("foobar"), 0x8594_4171_f739_67e8);
+
+        let store = Arc::new(MemoryStateStore::default());
+        let dedup = DedupFilter::new(Duration::from_secs(60)).state_store(store.clone());
+        assert!(dedup.check("app\ntimeout").is_ok());
+        assert!(store.get(&format!("dedup:{:x}", stable_hash("app\ntimeout"))).is_some());
+    }
+
+    #[test]
+    fn level_range_bounds_both_ends() {
+        let range = LevelRange::new(Level::WARN, Level::INFO);
+        assert!(!range.contains(&Level::ERROR));
+        assert!(range.contains(&Level::WARN));
+        assert!(range.contains(&Level::INFO));
+        assert!(!range.contains(&Level::DEBUG));
+        assert!(LevelRange::at_most(Level::WARN).contains(&Level::ERROR));
+    }
+
+    #[test]
+    fn missing_values_only_pass_negative_filters() {
+        assert!(EventFilters::from(Regex::new(".*").unwrap()).process_missing().is_err());
+        let negative = EventFilters::new(None, Some(vec![Regex::new("^payment").unwrap()]));
+        assert!(negative.process_missing().is_ok());
+        assert!(negative.process("payment_processing").is_err());
+    }
+
+    #[test]
+    fn span_field_filter_matches_values() {
+        let filter = SpanFieldFilter::new("tenant_id", Regex::new("^vip$").unwrap());
+        assert!(filter.matches(Some(&json!("vip"))));
+        assert!(!filter.matches(Some(&json!("vip-trial"))));
+        assert!(!filter.matches(None));
+        assert!(filter.pass_if_missing().matches(None));
+        assert!(SpanFieldFilter::new("shard", Regex::new("^4[0-9]$").unwrap()).matches(Some(&json!(42))));
+    }
+
+    #[test]
+    fn level_directives_apply_the_longest_matching_target() {
+        let directives: LevelDirectives = "warn, myapp::db=info,myapp::db::pool=error".parse().unwrap();
+        assert!(directives.enabled(&Level::INFO, "myapp::db::query"));
+        assert!(!directives.enabled(&Level::WARN, "myapp::db::pool"));
+        assert!(directives.enabled(&Level::WARN, "other"));
+        assert!(!directives.enabled(&Level::INFO, "other"));
+
+        // Without a default, only the targets of the directives are sent.
+        let directives: LevelDirectives = "myapp::http=error".parse().unwrap();
+        assert!(directives.enabled(&Level::ERROR, "myapp::http"));
+        assert!(!directives.enabled(&Level::ERROR, "other"));
+
+        assert!("warn".parse::<LevelDirectives>().unwrap().enabled(&Level::ERROR, "any"));
+        assert!("myapp=loud".parse::<LevelDirectives>().is_err());
+        assert!("".parse::<LevelDirectives>().is_err());
+    }
+
+    #[test]
+    fn source_filters_match_an_exact_file_and_line() {
+        let filters = EventFilters::new(None, Some(vec![Regex::new(r"^src/poller\.rs:88$").unwrap()]));
+        assert!(filters.process(&source_location(Some("src/poller.rs"), Some(88))).is_err());
+        assert!(filters.process(&source_location(Some("src/poller.rs"), Some(89))).is_ok());
+        assert!(filters.process(&source_location(Some("src/poller.rs"), Some(880))).is_ok());
+    }
+
+    #[test]
+    fn source_filters_match_a_whole_file() {
+        let filters = EventFilters::new(None, Some(vec![Regex::new(r"^src/poller\.rs:").unwrap()]));
+        assert!(filters.process(&source_location(Some("src/poller.rs"), Some(1))).is_err());
+        assert!(filters.process(&source_location(Some("src/server.rs"), Some(88))).is_ok());
+        let filters = EventFilters::from(Regex::new(r"^src/poller\.rs:").unwrap());
+        assert!(filters.process(&source_location(Some("src/poller.rs"), Some(1))).is_ok());
+        assert!(filters.process(&source_location(None, None)).is_err());
+    }
+
+    /// Serializes the tests reading filters from the environment, which is shared by every test.
+    static ENV: Mutex<()> = Mutex::new(());
+
+    /// Runs `f` with the given filter variables set, and the others unset.
+    fn with_env<T>(vars: &[(&str, &str)], f: impl FnOnce() -> T) -> T {
+        let _lock = ENV.lock().unwrap_or_else(|e| e.into_inner());
+        let names = [
+            EnvFilters::TARGETS,
+            EnvFilters::TARGET_INCLUDE,
+            EnvFilters::TARGET_EXCLUDE,
+            EnvFilters::MESSAGE_INCLUDE,
+            EnvFilters::MESSAGE_EXCLUDE,
+            EnvFilters::FIELD_INCLUDE,
+            EnvFilters::FIELD_EXCLUDE,
+            EnvFilters::LEVEL,
+        ];
+        for name in names {
+            std::env::remove_var(name);
+        }
+        for (name, value) in vars {
+            std::env::set_var(name, value);
+        }
+        let result = f();
+        for (name, _) in vars {
+            std::env::remove_var(name);
+        }
+        result
+    }
+
+    #[test]
+    fn filters_are_read_from_the_environment() {
+        let env = with_env(
+            &[
+                ("WEBHOOK_TARGET_EXCLUDE", "^hyper"),
+                ("WEBHOOK_MESSAGE_INCLUDE", "disk"),
+                ("WEBHOOK_LEVEL", "warn,app::db=info"),
+            ],
+            EnvFilters::from_env,
+        )
+        .unwrap();
+        let target_filters = env.target_filters.unwrap();
+        assert!(target_filters.process("hyper::client").is_err());
+        assert!(target_filters.process("app::db").is_ok());
+        let message_filters = env.message_filters.unwrap();
+        assert!(message_filters.process("disk almost full").is_ok());
+        assert!(message_filters.process("cpu almost idle").is_err());
+        assert!(env.event_by_field_filters.is_none());
+        assert_eq!(env.level_filter.as_deref(), Some("warn,app::db=info"));
+
+        assert!(with_env(&[], EnvFilters::from_env).unwrap().target_filters.is_none());
+        let error = with_env(&[("WEBHOOK_LEVEL", "loud")], EnvFilters::from_env).unwrap_err();
+        assert!(matches!(error, EnvFilterError::InvalidLevel { var: "WEBHOOK_LEVEL", .. }));
+        let error = with_env(&[("WEBHOOK_FIELD_EXCLUDE", "(")], EnvFilters::from_env).unwrap_err();
+        assert!(matches!(error, EnvFilterError::InvalidRegex { var: "WEBHOOK_FIELD_EXCLUDE", .. }));
+    }
+
+    #[test]
+    fn targets_are_read_from_the_environment_as_module_prefixes() {
+        let env = with_env(
+            &[("WEBHOOK_TARGETS", "app::db, hyper"), ("WEBHOOK_TARGET_EXCLUDE", "::pool$")],
+            EnvFilters::from_env,
+        )
+        .unwrap();
+        let target_filters = env.target_filters.unwrap();
+        for target in ["app::db", "app::db::query", "hyper", "hyper::client"] {
+            assert!(target_filters.process(target).is_ok(), "{}", target);
+        }
+        for target in ["app::dbx", "app", "hyperx", "app::db::pool"] {
+            assert!(target_filters.process(target).is_err(), "{}", target);
+        }
+
+        for value in ["app::db,", "app:::db", "app.*"] {
+            let error = with_env(&[("WEBHOOK_TARGETS", value)], EnvFilters::from_env).unwrap_err();
+            assert!(matches!(error, EnvFilterError::InvalidTarget { var: "WEBHOOK_TARGETS", .. }), "{}", value);
+        }
+    }
+
+    #[test]
+    fn filter_errors_describe_why_events_were_not_sent() {
+        use std::error::Error;
+
+        assert!(matches!(
+            EventFilters::new(None, Some(vec![Regex::new("noisy").unwrap()])).process("noisy::module"),
+            Err(FilterError::NegativeMatchFailed)
+        ));
+        assert_eq!(FilterError::RateLimited.to_string(), "dropped by the rate limit");
+        assert!(FilterError::Duplicate.source().is_none());
+
+        let serde_error = serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+        let description = format!("failed to serialize the fields: {}", serde_error);
+        let error = FilterError::from(serde_error);
+        assert!(matches!(error, FilterError::SerializationFailed(_)));
+        assert_eq!(error.to_string(), description);
+        assert!(error.source().unwrap().is::<serde_json::Error>());
     }
 }
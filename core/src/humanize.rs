@@ -0,0 +1,123 @@
+use serde_json::Value;
+
+/// The unit of a numeric field, used to render its value in human-friendly units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldUnit {
+    /// A duration in milliseconds, rendered as e.g. `850ms`, `1.2s` or `3.5m`.
+    Milliseconds,
+    /// A duration in seconds, rendered as e.g. `850ms`, `1.2s` or `3.5m`.
+    Seconds,
+    /// A size in bytes, rendered as e.g. `512 B` or `4.5 MiB`.
+    Bytes,
+}
+
+impl FieldUnit {
+    fn format(self, value: f64) -> String {
+        match self {
+            FieldUnit::Milliseconds => format_duration(value / 1000.0),
+            FieldUnit::Seconds => format_duration(value),
+            FieldUnit::Bytes => format_bytes(value),
+        }
+    }
+}
+
+fn format_duration(seconds: f64) -> String {
+    if seconds.abs() < 1.0 {
+        format!("{}ms", (seconds * 1000.0).round())
+    } else if seconds.abs() < 60.0 {
+        format!("{:.1}s", seconds)
+    } else if seconds.abs() < 3600.0 {
+        format!("{:.1}m", seconds / 60.0)
+    } else {
+        format!("{:.1}h", seconds / 3600.0)
+    }
+}
+
+fn format_bytes(bytes: f64) -> String {
+    const UNITS: [&str; 5] = ["KiB", "MiB", "GiB", "TiB", "PiB"];
+    if bytes.abs() < 1024.0 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes;
+    let mut unit = UNITS[0];
+    for next in UNITS {
+        value /= 1024.0;
+        unit = next;
+        if value.abs() < 1024.0 {
+            break;
+        }
+    }
+    format!("{:.1} {}", value, unit)
+}
+
+/// Rules for rendering numeric event and span fields in human-friendly units ("1.2s", "4.5 MiB")
+/// instead of raw numbers, selected by the suffix of the field's name.
+///
+/// The default rules render fields named `*_ms` as milliseconds, `*_duration` as seconds, and
+/// `*_bytes` as bytes. Fields whose value is not a number are left untouched.
+#[derive(Debug, Clone)]
+pub struct HumanizeFields {
+    rules: Vec<(String, FieldUnit)>,
+}
+
+impl HumanizeFields {
+    /// Create a set of rules without any rule.
+    pub fn empty() -> Self {
+        Self { rules: Vec::new() }
+    }
+
+    /// Render the fields whose name ends with `suffix` in the given unit. Rules added first take
+    /// precedence when several suffixes match.
+    pub fn rule(mut self, suffix: impl Into<String>, unit: FieldUnit) -> Self {
+        self.rules.push((suffix.into(), unit));
+        self
+    }
+
+    /// The humanized value of the field, or `None` if it should be sent as-is.
+    pub(crate) fn apply(&self, key: &str, value: &Value) -> Option<Value> {
+        let number = value.as_f64()?;
+        let (_, unit) = self.rules.iter().find(|(suffix, _)| key.ends_with(suffix.as_str()))?;
+        Some(Value::String(unit.format(number)))
+    }
+}
+
+impl Default for HumanizeFields {
+    fn default() -> Self {
+        Self::empty()
+            .rule("_ms", FieldUnit::Milliseconds)
+            .rule("_duration", FieldUnit::Seconds)
+            .rule("_bytes", FieldUnit::Bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use crate::humanize::{FieldUnit, HumanizeFields};
+
+    #[test]
+    fn numeric_fields_are_rendered_in_the_unit_of_their_suffix() {
+        let humanize = HumanizeFields::default();
+        let humanized = |key: &str, value: Value| humanize.apply(key, &value);
+        assert_eq!(humanized("latency_ms", json!(850)), Some(json!("850ms")));
+        assert_eq!(humanized("latency_ms", json!(1234)), Some(json!("1.2s")));
+        assert_eq!(humanized("job_duration", json!(210)), Some(json!("3.5m")));
+        assert_eq!(humanized("uptime_duration", json!(7200.0)), Some(json!("2.0h")));
+        assert_eq!(humanized("body_bytes", json!(512)), Some(json!("512 B")));
+        assert_eq!(humanized("body_bytes", json!(4_718_592)), Some(json!("4.5 MiB")));
+        // Other fields, and fields which are not numbers, are sent as-is.
+        assert_eq!(humanized("retries", json!(3)), None);
+        assert_eq!(humanized("latency_ms", json!("slow")), None);
+    }
+
+    #[test]
+    fn rules_added_first_take_precedence() {
+        let humanize = HumanizeFields::empty()
+            .rule("_total_ms", FieldUnit::Seconds)
+            .rule("_ms", FieldUnit::Milliseconds);
+        assert_eq!(humanize.apply("wait_total_ms", &json!(90)), Some(json!("1.5m")));
+        assert_eq!(humanize.apply("wait_ms", &json!(90)), Some(json!("90ms")));
+        assert_eq!(HumanizeFields::empty().apply("wait_ms", &json!(90)), None);
+    }
+}
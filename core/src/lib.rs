@@ -1,33 +1,270 @@
 use std::fmt::Debug;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
 
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use reqwest::Identity;
+pub use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+pub use reqwest::{Client, RequestBuilder, StatusCode};
 use serde_json::Value;
 use tracing::Level;
 
-pub use filters::EventFilters;
+use clock::{Clock, SystemClock};
+use template::Template;
+pub use filters::{EnvFilterError, EventFilters};
+pub use layer::BuildError;
 pub use worker::BackgroundWorker;
+pub use worker::{
+    BackoffJitter, CapturedMessage, ConnectivityCheck, ConnectivityError, DeliveryError, EnrichFuture, MessageEnricher,
+    ResponseError, WorkerGuard, DEFAULT_GUARD_TIMEOUT, DEFAULT_MAX_RETRIES, DEFAULT_REQUEST_TIMEOUT,
+};
 pub use worker::WorkerMessage;
+pub use queue::BackpressurePolicy;
+pub use state::{MemoryStateStore, StateStore};
+pub use timestamp::TimestampFormat;
 
 // mod aws_lambda;
+pub mod clock;
 pub mod filters;
+pub mod humanize;
 pub mod layer;
+pub mod metrics;
+mod pool;
+mod queue;
+pub mod spec;
+pub mod state;
+pub mod template;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+#[cfg(feature = "syslog")]
+pub mod syslog;
+pub mod timestamp;
 mod worker;
 
 pub type ChannelSender = tokio::sync::mpsc::UnboundedSender<WorkerMessage>;
 pub type ChannelReceiver = tokio::sync::mpsc::UnboundedReceiver<WorkerMessage>;
 
 /// Send a message to a webhook endpoint.
+///
+/// The body sent to the webhook is exactly the output of `serialize`, or of `serialize_parts` for
+/// messages split into several requests, so tests for a custom `WebhookMessageFactory` can assert on
+/// it directly.
 pub trait WebhookMessage: Debug + Send + Sync {
     fn webhook_url(&self) -> &str;
+
+    /// Serialize the message into the body of the request sent to the webhook.
     fn serialize(&self) -> String;
+
+    /// Serialize the message into the bodies of several requests, sent one after the other, when it
+    /// exceeds the limits of the destination, e.g. as continuation messages numbered `(1/3)`,
+    /// `(2/3)`, ... rather than cutting off the end of a long stack trace. Delivery stops at the
+    /// first part that fails.
+    ///
+    /// By default, the message is sent whole, as serialized by `serialize`.
+    fn serialize_parts(&self) -> Vec<String> {
+        vec![self.serialize()]
+    }
+
+    /// The value of the `Content-Type` header sent with this message.
+    fn content_type(&self) -> &str {
+        "application/json"
+    }
+
+    /// The level of the event this message was created from, if known.
+    fn level(&self) -> Option<Level> {
+        None
+    }
+
+    /// The key of the span of the events of this message, from `WebhookMessageInputs::span_key`, if
+    /// they all belong to the same span.
+    fn span_key(&self) -> Option<u64> {
+        None
+    }
+
+    /// Append a line to the text of the message, e.g. a mention or the link to a runbook added by a
+    /// `MessageEnricher`, in the markup of the destination, e.g. `<@U123>` for a Slack mention.
+    /// Messages which do not support it ignore it, as by default.
+    fn append_text(&mut self, text: &str) {
+        let _ = text;
+    }
 }
 
+/// Produce a webhook message from the data extracted from a tracing event.
 pub trait WebhookMessageFactory {
-    fn create<'a>(&'a self, inputs: WebhookMessageInputs) -> Box<dyn WebhookMessage>;
+    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage;
+
+    /// Produce a single webhook message from the data extracted from several tracing events, in the
+    /// order in which they were emitted, for layers batching events with
+    /// `WebhookLayerBuilder::batch_window`. A batch holds at least two events; a single event is
+    /// sent with `create`.
+    fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage;
+}
+
+/// Configuration describing how to reach a webhook endpoint.
+pub trait Config: Send + Sync {
+    fn webhook_url(&self) -> &str;
+
+    /// The client certificate and key presented to the webhook endpoint, for endpoints that
+    /// require mutual TLS.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    fn tls_identity(&self) -> Option<Identity> {
+        None
+    }
+
+    fn new_from_env() -> Self
+    where
+        Self: Sized;
+
+    /// Like `new_from_env`, but returns an error rather than panicking when the environment lacks a
+    /// required variable, used by `WebhookLayerBuilder::build` when no config is given.
+    ///
+    /// Defaults to `new_from_env`, which may still panic.
+    fn try_new_from_env() -> Result<Self, BuildError>
+    where
+        Self: Sized,
+    {
+        Ok(Self::new_from_env())
+    }
+
+    /// Several webhook URLs for the same destination, with their relative weights, e.g. multiple
+    /// incoming webhooks for the same channel to raise the effective rate limit.
+    ///
+    /// When not empty, the messages addressed to `webhook_url` are spread across these URLs by
+    /// weighted round-robin instead, each attempt at delivering a message using the next URL. A URL
+    /// responding with `429 Too Many Requests` is skipped for a few seconds, so its share of the
+    /// messages goes to the other URLs until its rate limit resets.
+    fn webhook_urls(&self) -> &[(String, u32)] {
+        &[]
+    }
+
+    /// The number of attempts at delivering each message before giving up on it, with an exponential
+    /// backoff between attempts. Defaults to 10.
+    fn max_retries(&self) -> usize {
+        DEFAULT_MAX_RETRIES
+    }
+
+    /// How long to wait for the webhook to respond to a request before counting the attempt as
+    /// failed and retrying, so an endpoint that never responds cannot stall the worker. Defaults to
+    /// 30 seconds.
+    fn request_timeout(&self) -> Duration {
+        DEFAULT_REQUEST_TIMEOUT
+    }
+
+    /// How long to keep attempting to deliver each message, from its first attempt, before giving up
+    /// on it, even if it was attempted fewer than `max_retries` times, so a long outage cannot hold
+    /// up the next messages. The last attempt is cut short when it would outlast it. Defaults to no
+    /// limit.
+    fn retry_deadline(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Whether a message the webhook responded to with the given unsuccessful status is attempted
+    /// again. Defaults to retrying server errors (`5xx`), `429 Too Many Requests` after the delay
+    /// the webhook asked for (or with the exponential backoff if it asked for over a minute), and
+    /// `408 Request Timeout`, and to giving up on other client errors (`4xx`), which would fail the
+    /// same way. Requests which could not be sent or timed out are always retried.
+    fn retryable_status(&self, status: StatusCode) -> bool {
+        !status.is_client_error() || status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::REQUEST_TIMEOUT
+    }
+
+    /// How messages for events of the given level are decorated, e.g. with a color or a mention,
+    /// by destinations supporting it, such as Slack. Messages are left undecorated by default.
+    fn presentation(&self, _level: Level) -> Presentation {
+        Presentation::default()
+    }
+
+    /// Whether the fields of events are sent as pretty-printed JSON, rather than compact JSON which
+    /// leaves more room for the fields themselves under the length limits of destinations. Defaults
+    /// to `true`.
+    fn pretty_metadata(&self) -> bool {
+        true
+    }
+
+    /// How to render the time events were recorded in their messages, e.g.
+    /// `TimestampFormat::rfc3339()`. Messages have no timestamp by default, leaving the destination
+    /// to show when it received them.
+    fn timestamp_format(&self) -> Option<&TimestampFormat> {
+        None
+    }
+
+    /// Whether the worker prints the serialized body of each message to stderr instead of sending
+    /// it, e.g. to tune filters in staging without posting to the channel. Messages are counted as
+    /// sent. Defaults to `false`.
+    fn dry_run(&self) -> bool {
+        false
+    }
+
+    /// How the exponential backoff between attempts at delivering a message is randomized.
+    /// Defaults to `BackoffJitter::None`, keeping the delays deterministic; `BackoffJitter::Full`
+    /// keeps many instances retrying against the same throttled webhook out of lockstep.
+    fn backoff_jitter(&self) -> BackoffJitter {
+        BackoffJitter::default()
+    }
+
+    /// A key identifying this service or stream, sent in the `routing_key_header` header of every
+    /// request, so that a webhook receiver aggregating several sources can demultiplex them.
+    fn routing_key(&self) -> Option<&str> {
+        None
+    }
+
+    /// The name of the header carrying the `routing_key`.
+    fn routing_key_header(&self) -> &str {
+        "X-Routing-Key"
+    }
+
+    /// Headers added to every request, e.g. an `Authorization` header for an endpoint behind an
+    /// authenticating proxy, or the token of a Mattermost webhook. None by default.
+    fn headers(&self) -> &HeaderMap {
+        static NO_HEADERS: OnceLock<HeaderMap> = OnceLock::new();
+        NO_HEADERS.get_or_init(HeaderMap::new)
+    }
+
+    /// Transform each request just before it is sent, given the serialized body of the request.
+    ///
+    /// This is the extension point for destinations requiring headers computed from the payload,
+    /// such as an HMAC signature. By default, the request is sent unchanged.
+    fn sign_request(&self, request: RequestBuilder, _body: &[u8]) -> RequestBuilder {
+        request
+    }
+
+    /// Handle the response of the webhook to a delivered message, given its status and body, e.g. to
+    /// parse and record acknowledgement data such as the `ts` of a Slack message. A streamed body,
+    /// such as line-delimited JSON, is read in full before calling this.
+    ///
+    /// By default, the response is ignored.
+    fn handle_response(&self, _message: &dyn WebhookMessage, _status: StatusCode, _body: &str) {}
+
+    /// The failure reported in the body of a successful response, for APIs reporting failures with a
+    /// `200 OK`, such as Slack's Web API responding with `{"ok":false,"error":"channel_not_found"}`.
+    /// The message is then retried or given up on depending on the kind of failure, and the error
+    /// logged with the `log-errors` feature.
+    ///
+    /// By default, successful responses are not inspected.
+    fn response_error(&self, _status: StatusCode, _body: &str) -> Option<ResponseError> {
+        None
+    }
+
+    /// Amend the serialized body of a message right before it is sent, e.g. to post it in the thread
+    /// of an earlier message of the same span, recorded by `handle_response`.
+    ///
+    /// By default, the body is sent unchanged.
+    fn prepare_body(&self, _message: &dyn WebhookMessage, body: String) -> String {
+        body
+    }
+
+    /// The source of the current time for the time-based features of the layer and the worker.
+    ///
+    /// This is the real clock of the system by default; tests can use a `MockClock` instead.
+    fn clock(&self) -> Arc<dyn Clock> {
+        Arc::new(SystemClock)
+    }
 }
 
 /// The data expected to be available for message producers.
+#[derive(Clone, Debug)]
 pub struct WebhookMessageInputs {
     pub app_name: String,
+    pub webhook_url: String,
     pub message: String,
     pub target: String,
     pub span: String,
@@ -35,6 +272,49 @@ pub struct WebhookMessageInputs {
     pub source_line: u32,
     pub source_file: String,
     pub event_level: Level,
+    /// The chain of errors that caused the event's error, outermost first, recorded in fields
+    /// named `error.source.0`, `error.source.1`, and so on, or else walked from the `source` of an
+    /// `error` field recorded as a `dyn Error`, e.g. `error!(error = &err as &dyn Error, "...")`.
+    /// Empty if neither was recorded.
+    pub error_sources: Vec<String>,
+    /// The position of this message among every message forwarded by the layer since the process
+    /// started, if sequence numbers are enabled. A gap in the sequence means messages were dropped.
+    pub sequence: Option<u64>,
+    /// Whether the destination should notify (e.g. ping or play a sound) for this message. This is
+    /// `false` for events below the layer's `notify_above` level; factories for destinations without
+    /// per-message control over notifications ignore it.
+    pub notify: bool,
+    /// The decorations configured for the level of the event with `Config::presentation`.
+    pub presentation: Presentation,
+    /// A key identifying the span of the event, if any. Unlike the ids of spans, which are reused
+    /// once they close, keys are never reused.
+    pub span_key: Option<u64>,
+    /// The time the event was recorded, rendered with the `timestamp_format` of the config, if it
+    /// has one.
+    pub timestamp: Option<String>,
+}
+
+/// How a message is decorated by destinations supporting it, such as Slack, as configured for the
+/// level of its event with `Config::presentation`. Destinations ignore the decorations they have
+/// no room for.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Presentation {
+    /// The color of the message, e.g. `#439FE0`, overriding the default color of the destination.
+    pub color: Option<String>,
+    /// The mention prepended to the message, e.g. `<!here>` to page the channel on errors.
+    pub mention: Option<String>,
+    /// The emoji prepended to the message, e.g. `:red_circle:`, as a visual indicator of its level.
+    pub emoji: Option<String>,
+    /// Whether the message carries the fields of its event as compact JSON, in `raw_json`, for
+    /// tooling consuming it.
+    pub include_raw_json: bool,
+    /// The fields of the event and its spans as compact JSON, neither truncated, humanized, nor
+    /// capped, filled in by the layer if `include_raw_json` is set. Fields matching the field
+    /// exclusion filters are left out.
+    pub raw_json: Option<String>,
+    /// The template of a `TemplateConfig`, which the messages of the `TemplateFactory` are rendered
+    /// with.
+    pub template: Option<Arc<Template>>,
 }
 
 #[allow(dead_code)]
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The upper bounds, in bytes, of the buckets of the `webhook_payload_bytes` histogram. Payloads
+/// larger than the last bound are counted in a final, unbounded bucket.
+pub const PAYLOAD_SIZE_BUCKETS: [u64; 9] = [256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536];
+
+/// Records the distribution of the serialized size of the payloads sent by the worker.
+#[derive(Debug, Default)]
+pub(crate) struct PayloadSizes {
+    count: AtomicU64,
+    total_bytes: AtomicU64,
+    max_bytes: AtomicU64,
+    buckets: [AtomicU64; PAYLOAD_SIZE_BUCKETS.len() + 1],
+}
+
+impl PayloadSizes {
+    pub(crate) fn record(&self, bytes: usize) {
+        let bytes = bytes as u64;
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.total_bytes.fetch_add(bytes, Ordering::Relaxed);
+        self.max_bytes.fetch_max(bytes, Ordering::Relaxed);
+        let bucket = PAYLOAD_SIZE_BUCKETS
+            .iter()
+            .position(|&bound| bytes <= bound)
+            .unwrap_or(PAYLOAD_SIZE_BUCKETS.len());
+        self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self) -> PayloadSizeHistogram {
+        let mut buckets = [0; PAYLOAD_SIZE_BUCKETS.len() + 1];
+        for (count, bucket) in buckets.iter_mut().zip(&self.buckets) {
+            *count = bucket.load(Ordering::Relaxed);
+        }
+        PayloadSizeHistogram {
+            count: self.count.load(Ordering::Relaxed),
+            total_bytes: self.total_bytes.load(Ordering::Relaxed),
+            max_bytes: self.max_bytes.load(Ordering::Relaxed),
+            buckets,
+        }
+    }
+}
+
+/// A snapshot of the `webhook_payload_bytes` histogram: the serialized size of the payloads sent
+/// by the worker, useful for tuning size limits and truncation thresholds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PayloadSizeHistogram {
+    /// The number of payloads recorded.
+    pub count: u64,
+    /// The sum of the sizes of every payload recorded, in bytes.
+    pub total_bytes: u64,
+    /// The size of the largest payload recorded, in bytes.
+    pub max_bytes: u64,
+    /// The number of payloads in each bucket. `buckets[i]` counts the payloads no larger than
+    /// `PAYLOAD_SIZE_BUCKETS[i]` (and larger than the previous bound), and the last bucket counts
+    /// the payloads larger than every bound.
+    pub buckets: [u64; PAYLOAD_SIZE_BUCKETS.len() + 1],
+}
+
+/// Counts the messages handled by the worker, from being enqueued to being delivered or given up on.
+#[derive(Debug, Default)]
+pub(crate) struct DeliveryCounters {
+    enqueued: AtomicU64,
+    sent: AtomicU64,
+    retried: AtomicU64,
+    failed: AtomicU64,
+}
+
+impl DeliveryCounters {
+    pub(crate) fn record_enqueued(&self) {
+        self.enqueued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_sent(&self) {
+        self.sent.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_retry(&self) {
+        self.retried.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_failed(&self) {
+        self.failed.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, dropped: u64) -> WorkerMetrics {
+        WorkerMetrics {
+            enqueued: self.enqueued.load(Ordering::Relaxed),
+            sent: self.sent.load(Ordering::Relaxed),
+            retried: self.retried.load(Ordering::Relaxed),
+            failed: self.failed.load(Ordering::Relaxed),
+            dropped,
+        }
+    }
+}
+
+/// A snapshot of the delivery counters of a `BackgroundWorker`, e.g. to export them to a metrics
+/// system or log them periodically.
+///
+/// When batching, `enqueued` counts events while the other counters count the combined messages.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct WorkerMetrics {
+    /// The number of messages enqueued for the worker, by the layer or `BackgroundWorker::send`.
+    pub enqueued: u64,
+    /// The number of messages delivered.
+    pub sent: u64,
+    /// The number of attempts at delivering a message after a failed one.
+    pub retried: u64,
+    /// The number of messages given up on, because every attempt failed, the webhook rejected
+    /// them, their deadline elapsed, or their serializer panicked.
+    pub failed: u64,
+    /// The number of messages dropped because the bounded queue was full.
+    pub dropped: u64,
+}
@@ -0,0 +1,123 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::clock::Clock;
+
+/// How long a webhook URL is skipped after it responds with `429 Too Many Requests`.
+const RATE_LIMITED_COOLDOWN: Duration = Duration::from_secs(5);
+
+/// Spreads the messages for a single destination across several webhook URLs by smooth weighted
+/// round-robin, skipping the URLs that were recently rate limited.
+pub(crate) struct WebhookPool {
+    /// The URL that messages for this destination are addressed to.
+    primary: String,
+    entries: Vec<PoolEntry>,
+    clock: Arc<dyn Clock>,
+}
+
+struct PoolEntry {
+    url: String,
+    weight: i64,
+    current_weight: i64,
+    rate_limited_until: Option<Instant>,
+}
+
+impl PoolEntry {
+    fn is_available(&self, now: Instant) -> bool {
+        self.rate_limited_until.is_none_or(|until| until <= now)
+    }
+}
+
+impl WebhookPool {
+    pub(crate) fn new(primary: &str, urls: &[(String, u32)], clock: Arc<dyn Clock>) -> Self {
+        Self {
+            primary: primary.to_string(),
+            entries: urls
+                .iter()
+                .map(|(url, weight)| PoolEntry {
+                    url: url.clone(),
+                    weight: i64::from((*weight).max(1)),
+                    current_weight: 0,
+                    rate_limited_until: None,
+                })
+                .collect(),
+            clock,
+        }
+    }
+
+    /// The URL to send the next attempt at delivering a message addressed to `url` to.
+    ///
+    /// Messages addressed to any URL other than the one of this destination are sent as-is.
+    pub(crate) fn select<'a>(&'a mut self, url: &'a str) -> &'a str {
+        if self.entries.is_empty() || url != self.primary {
+            return url;
+        }
+        let now = self.clock.now();
+        // If every URL is rate limited, fall back to picking among all of them.
+        let any_available = self.entries.iter().any(|entry| entry.is_available(now));
+        let mut total_weight = 0;
+        let mut selected: Option<usize> = None;
+        for i in 0..self.entries.len() {
+            if any_available && !self.entries[i].is_available(now) {
+                continue;
+            }
+            self.entries[i].current_weight += self.entries[i].weight;
+            total_weight += self.entries[i].weight;
+            if selected.is_none_or(|s| self.entries[i].current_weight > self.entries[s].current_weight) {
+                selected = Some(i);
+            }
+        }
+        let selected = &mut self.entries[selected.expect("pool has at least one entry")];
+        selected.current_weight -= total_weight;
+        &selected.url
+    }
+
+    /// Skip `url` for a while, after it responded with `429 Too Many Requests`.
+    pub(crate) fn rate_limited(&mut self, url: &str) {
+        if let Some(entry) = self.entries.iter_mut().find(|entry| entry.url == url) {
+            entry.rate_limited_until = Some(self.clock.now() + RATE_LIMITED_COOLDOWN);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::clock::MockClock;
+    use crate::pool::{WebhookPool, RATE_LIMITED_COOLDOWN};
+
+    fn pool(clock: Arc<MockClock>) -> WebhookPool {
+        let urls = [("https://a.example.com".to_string(), 2), ("https://b.example.com".to_string(), 1)];
+        WebhookPool::new("https://example.com/webhook", &urls, clock)
+    }
+
+    fn selected(pool: &mut WebhookPool, attempts: usize) -> Vec<String> {
+        (0..attempts).map(|_| pool.select("https://example.com/webhook").to_string()).collect()
+    }
+
+    #[test]
+    fn attempts_are_spread_across_the_urls_by_weight() {
+        let mut pool = pool(Arc::new(MockClock::new()));
+        let (a, b) = ("https://a.example.com", "https://b.example.com");
+        assert_eq!(selected(&mut pool, 6), [a, b, a, a, b, a]);
+        // Messages addressed to other URLs are sent as-is.
+        assert_eq!(pool.select("https://example.com/other"), "https://example.com/other");
+    }
+
+    #[test]
+    fn rate_limited_urls_are_skipped_until_their_cooldown_elapsed() {
+        let clock = Arc::new(MockClock::new());
+        let mut pool = pool(clock.clone());
+        pool.rate_limited("https://a.example.com");
+        assert_eq!(selected(&mut pool, 3), ["https://b.example.com"; 3]);
+
+        // Every URL being rate limited, they are all picked from.
+        pool.rate_limited("https://b.example.com");
+        assert!(selected(&mut pool, 3).contains(&"https://a.example.com".to_string()));
+
+        clock.advance(RATE_LIMITED_COOLDOWN);
+        pool.rate_limited("https://b.example.com");
+        assert_eq!(selected(&mut pool, 2), ["https://a.example.com"; 2]);
+    }
+}
@@ -0,0 +1,248 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Condvar, Mutex, OnceLock};
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use tokio::sync::Notify;
+
+use crate::{ChannelSender, DeliveryError, WorkerMessage};
+
+/// What happens to an event when the queue of messages waiting for the worker is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum BackpressurePolicy {
+    /// Drop the new event, keeping the messages already queued.
+    DropNewest,
+    /// Drop the oldest queued message to make room for the new event, so the most recent events are
+    /// delivered.
+    #[default]
+    DropOldest,
+    /// Block the thread emitting the event until the worker makes room for it.
+    ///
+    /// `on_event` is synchronous, so this blocks the emitting thread itself rather than yielding to
+    /// the runtime: it must not be used when events are emitted from the thread running the worker,
+    /// e.g. on a current-thread runtime, which would deadlock. Nothing would make room while the
+    /// worker is not running either, so the new event is dropped instead until it is started, and
+    /// once it stopped.
+    Block,
+}
+
+/// Whether the worker receiving the messages of a queue is running, which a thread blocked by
+/// `BackpressurePolicy::Block` waits for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum WorkerState {
+    NotStarted,
+    Running,
+    Stopped,
+}
+
+impl WorkerState {
+    /// The state of the worker receiving on the channel of the given sender, and spawned on the
+    /// runtime set once it is started.
+    pub(crate) fn of(sender: &ChannelSender, runtime: &OnceLock<Handle>) -> Self {
+        if sender.is_closed() {
+            WorkerState::Stopped
+        } else if runtime.get().is_none() {
+            WorkerState::NotStarted
+        } else {
+            WorkerState::Running
+        }
+    }
+}
+
+/// Holds at most `capacity` messages waiting for the worker, applying a `BackpressurePolicy` when
+/// full.
+///
+/// Messages enqueued by a layer built with `WebhookLayerBuilder::bounded`, or with
+/// `BackgroundWorker::send`, wait here rather than in the unbounded channel to the worker, which
+/// only carries the messages sent on it directly, such as `WorkerMessage::Shutdown`.
+#[derive(Debug)]
+pub(crate) struct BoundedQueue {
+    capacity: usize,
+    policy: BackpressurePolicy,
+    messages: Mutex<VecDeque<WorkerMessage>>,
+    /// Wakes the worker when a message is enqueued.
+    available: Notify,
+    /// Wakes the threads blocked by `BackpressurePolicy::Block` when the worker takes a message.
+    space: Condvar,
+    dropped: AtomicU64,
+}
+
+/// Why a message was not enqueued.
+#[derive(Debug)]
+pub(crate) enum PushError {
+    /// The queue was full, and the message was dropped according to the policy.
+    Full,
+    /// The worker stopped, so the message would never be received.
+    Closed(WorkerMessage),
+}
+
+impl BoundedQueue {
+    pub(crate) fn new(capacity: usize, policy: BackpressurePolicy) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            policy,
+            messages: Mutex::default(),
+            available: Notify::new(),
+            space: Condvar::new(),
+            dropped: AtomicU64::default(),
+        }
+    }
+
+    /// Enqueues a message, making room for it according to the policy if the queue is full.
+    /// `worker` tells whether the worker is running, so a thread does not block waiting for one
+    /// which will never make room.
+    pub(crate) fn push(&self, message: WorkerMessage, worker: impl Fn() -> WorkerState) -> Result<(), PushError> {
+        if worker() == WorkerState::Stopped {
+            return Err(PushError::Closed(message));
+        }
+        let mut messages = self.messages.lock().unwrap_or_else(|e| e.into_inner());
+        if messages.len() >= self.capacity {
+            match self.policy {
+                BackpressurePolicy::DropNewest => return Err(self.drop_newest(message)),
+                BackpressurePolicy::DropOldest => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    if let Some(oldest) = messages.pop_front() {
+                        dropped(oldest);
+                    }
+                }
+                BackpressurePolicy::Block => {
+                    while messages.len() >= self.capacity {
+                        match worker() {
+                            WorkerState::Running => {}
+                            WorkerState::NotStarted => return Err(self.drop_newest(message)),
+                            WorkerState::Stopped => return Err(PushError::Closed(message)),
+                        }
+                        messages = self
+                            .space
+                            .wait_timeout(messages, Duration::from_millis(100))
+                            .unwrap_or_else(|e| e.into_inner())
+                            .0;
+                    }
+                }
+            }
+        }
+        messages.push_back(message);
+        drop(messages);
+        self.available.notify_one();
+        Ok(())
+    }
+
+    /// Drops a message rather than enqueuing it.
+    fn drop_newest(&self, message: WorkerMessage) -> PushError {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+        dropped(message);
+        PushError::Full
+    }
+
+    /// Takes the oldest queued message, if any.
+    pub(crate) fn pop(&self) -> Option<WorkerMessage> {
+        let message = self.messages.lock().unwrap_or_else(|e| e.into_inner()).pop_front();
+        if message.is_some() {
+            self.space.notify_one();
+        }
+        message
+    }
+
+    /// Waits for a message to be enqueued, and takes it.
+    pub(crate) async fn recv(&self) -> WorkerMessage {
+        loop {
+            if let Some(message) = self.pop() {
+                return message;
+            }
+            // A message enqueued since `pop` leaves a permit, so this does not miss it.
+            self.available.notified().await;
+        }
+    }
+
+    /// The number of messages waiting for the worker.
+    #[cfg(test)]
+    pub(crate) fn len(&self) -> usize {
+        self.messages.lock().unwrap_or_else(|e| e.into_inner()).len()
+    }
+
+    /// The number of messages dropped because the queue was full.
+    pub(crate) fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// Reports a message dropped to make room for another one to whoever waits for its delivery.
+fn dropped(message: WorkerMessage) {
+    if let WorkerMessage::DataWithDeadline { result, .. } = message {
+        let _ = result.send(Err(DeliveryError::Dropped));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::queue::{BackpressurePolicy, BoundedQueue, PushError, WorkerState};
+    use crate::{WebhookMessage, WorkerMessage};
+
+    #[derive(Debug)]
+    struct Text(&'static str);
+
+    impl WebhookMessage for Text {
+        fn webhook_url(&self) -> &str {
+            "https://example.com/webhook"
+        }
+
+        fn serialize(&self) -> String {
+            self.0.to_string()
+        }
+    }
+
+    fn message(text: &'static str) -> WorkerMessage {
+        WorkerMessage::Data(Box::new(Text(text)))
+    }
+
+    fn text(message: Option<WorkerMessage>) -> Option<String> {
+        match message {
+            Some(WorkerMessage::Data(payload)) => Some(payload.serialize()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn oldest_messages_are_evicted_when_full() {
+        let queue = BoundedQueue::new(2, BackpressurePolicy::DropOldest);
+        for body in ["first", "second", "third", "fourth"] {
+            assert!(queue.push(message(body), || WorkerState::Running).is_ok());
+            assert!(queue.len() <= 2);
+        }
+        assert_eq!(queue.dropped(), 2);
+        assert_eq!(text(queue.pop()).as_deref(), Some("third"));
+        assert_eq!(text(queue.pop()).as_deref(), Some("fourth"));
+        assert!(queue.pop().is_none());
+    }
+
+    #[test]
+    fn newest_messages_are_rejected_when_full() {
+        let queue = BoundedQueue::new(2, BackpressurePolicy::DropNewest);
+        assert!(queue.push(message("first"), || WorkerState::Running).is_ok());
+        assert!(queue.push(message("second"), || WorkerState::Running).is_ok());
+        assert!(matches!(queue.push(message("third"), || WorkerState::Running), Err(PushError::Full)));
+        assert_eq!((queue.len(), queue.dropped()), (2, 1));
+        assert_eq!(text(queue.pop()).as_deref(), Some("first"));
+        assert!(queue.push(message("fourth"), || WorkerState::Running).is_ok());
+        assert_eq!(text(queue.pop()).as_deref(), Some("second"));
+        assert_eq!(text(queue.pop()).as_deref(), Some("fourth"));
+    }
+
+    #[test]
+    fn blocked_threads_give_up_once_the_worker_stopped() {
+        let queue = BoundedQueue::new(1, BackpressurePolicy::Block);
+        assert!(queue.push(message("first"), || WorkerState::Running).is_ok());
+        assert!(matches!(queue.push(message("second"), || WorkerState::Stopped), Err(PushError::Closed(_))));
+        assert_eq!((queue.len(), queue.dropped()), (1, 0));
+    }
+
+    #[test]
+    fn threads_do_not_block_before_the_worker_started() {
+        let queue = BoundedQueue::new(1, BackpressurePolicy::Block);
+        assert!(queue.push(message("first"), || WorkerState::NotStarted).is_ok());
+        assert!(matches!(queue.push(message("second"), || WorkerState::NotStarted), Err(PushError::Full)));
+        assert_eq!((queue.len(), queue.dropped()), (1, 1));
+        assert_eq!(text(queue.pop()).as_deref(), Some("first"));
+    }
+}
@@ -0,0 +1,295 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+
+use regex::Regex;
+use serde::Deserialize;
+use tracing::Subscriber;
+use tracing_subscriber::registry::LookupSpan;
+use tracing_subscriber::Layer;
+
+use crate::filters::LevelDirectives;
+use crate::layer::WebhookLayer;
+use crate::{BackgroundWorker, Config, EventFilters, WebhookMessageFactory};
+
+/// A description of several webhook layers, e.g. deserialized from a configuration file, built
+/// into layers with `WebhookLayers::from_spec`.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct WebhookLayersSpec {
+    pub layers: Vec<LayerSpec>,
+}
+
+/// A description of a single webhook layer: its destination and filters.
+///
+/// Every filter is optional; the regular expressions and the level follow the same semantics as the
+/// corresponding environment variables of `WebhookLayerBuilder::filters_from_env`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LayerSpec {
+    /// The kind of destination, as registered with `WebhookLayers::kind`, e.g. `slack`.
+    pub kind: String,
+    pub app_name: String,
+    pub webhook_url: String,
+    #[serde(default)]
+    pub target_include: Option<String>,
+    #[serde(default)]
+    pub target_exclude: Option<String>,
+    #[serde(default)]
+    pub message_include: Option<String>,
+    #[serde(default)]
+    pub message_exclude: Option<String>,
+    #[serde(default)]
+    pub field_include: Option<String>,
+    #[serde(default)]
+    pub field_exclude: Option<String>,
+    /// Only send events at or above this level, e.g. `warn`.
+    #[serde(default)]
+    pub level: Option<String>,
+}
+
+/// A layer built from a `LayerSpec`, boxed so layers of different kinds can be combined.
+pub type BoxedLayer<S> = Box<dyn Layer<S> + Send + Sync>;
+
+/// Builds a layer from its spec, sending its messages to the given worker, or to a new worker which
+/// it then returns.
+type LayerConstructor<S> =
+    Box<dyn Fn(LayerSpec, Option<&BackgroundWorker>) -> Result<(BoxedLayer<S>, Option<BackgroundWorker>), Vec<String>>>;
+
+/// Builds several webhook layers at once from a `WebhookLayersSpec`, for config-file-driven setups
+/// with several destinations and filter sets.
+///
+/// Each kind of destination referenced by the spec must first be registered with `kind`.
+pub struct WebhookLayers<S> {
+    kinds: HashMap<String, LayerConstructor<S>>,
+}
+
+impl<S> WebhookLayers<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    pub fn new() -> Self {
+        Self { kinds: HashMap::new() }
+    }
+
+    /// Register a kind of destination, creating its config from the webhook URL of the spec, e.g.
+    /// `.kind::<SlackConfig, SlackLayer>("slack", SlackConfig::new)`.
+    pub fn kind<C, F>(mut self, kind: impl Into<String>, config: fn(String) -> C) -> Self
+    where
+        C: Config + 'static,
+        F: WebhookMessageFactory + Send + Sync + 'static,
+    {
+        let constructor = move |spec: LayerSpec, worker: Option<&BackgroundWorker>| {
+            let mut errors = Vec::new();
+            let mut regex = |name: &str, value: Option<String>| {
+                value.and_then(|value| {
+                    Regex::new(&value)
+                        .map_err(|e| errors.push(format!("invalid regular expression in {}: {}", name, e)))
+                        .ok()
+                })
+            };
+            let target_filters = EventFilters::from((
+                regex("target_include", spec.target_include),
+                regex("target_exclude", spec.target_exclude),
+            ));
+            let message_filters = EventFilters::from((
+                regex("message_include", spec.message_include),
+                regex("message_exclude", spec.message_exclude),
+            ));
+            let event_by_field_filters = EventFilters::from((
+                regex("field_include", spec.field_include),
+                regex("field_exclude", spec.field_exclude),
+            ));
+            if let Some(level) = &spec.level {
+                if LevelDirectives::from_str(level).is_err() {
+                    errors.push(format!("invalid level: {:?}", level));
+                }
+            }
+            if !errors.is_empty() {
+                return Err(errors);
+            }
+
+            let mut builder = WebhookLayer::<C, F>::builder(spec.app_name, target_filters)
+                .config(config(spec.webhook_url))
+                .message_filters(message_filters)
+                .event_by_field_filters(event_by_field_filters);
+            if let Some(level) = spec.level {
+                builder = builder.level_filters(level);
+            }
+            let (layer, worker) = match worker {
+                Some(worker) => (builder.build_with_worker(worker), None),
+                None => match builder.build() {
+                    Ok((layer, worker)) => (Ok(layer), Some(worker)),
+                    Err(e) => (Err(e), None),
+                },
+            };
+            let layer = layer.map_err(|e| vec![e.to_string()])?;
+            Ok((Box::new(layer) as BoxedLayer<S>, worker))
+        };
+        self.kinds.insert(kind.into(), Box::new(constructor));
+        self
+    }
+
+    /// Build every layer described by the spec, along with the background worker they share, which
+    /// must be started for messages to be sent.
+    ///
+    /// The worker is the one of the first layer, and the other layers are built with
+    /// `WebhookLayerBuilder::build_with_worker`, so their events are not batched. The layers can be
+    /// added to a registry at once, as a `Vec` of layers is itself a layer. If any layer of the spec
+    /// is invalid, or if it has no layers, returns an error describing every problem found, rather
+    /// than only the first one.
+    pub fn from_spec(&self, spec: WebhookLayersSpec) -> Result<(Vec<BoxedLayer<S>>, BackgroundWorker), SpecError> {
+        let mut layers = Vec::new();
+        let mut shared_worker = None;
+        let mut errors = Vec::new();
+        for (index, layer_spec) in spec.layers.into_iter().enumerate() {
+            let constructor = match self.kinds.get(&layer_spec.kind) {
+                Some(constructor) => constructor,
+                None => {
+                    errors.push(format!("layers[{}]: unknown kind {:?}", index, layer_spec.kind));
+                    continue;
+                }
+            };
+            match constructor(layer_spec, shared_worker.as_ref()) {
+                Ok((layer, worker)) => {
+                    layers.push(layer);
+                    shared_worker = shared_worker.or(worker);
+                }
+                Err(layer_errors) => {
+                    errors.extend(layer_errors.into_iter().map(|e| format!("layers[{}]: {}", index, e)));
+                }
+            }
+        }
+        match shared_worker {
+            Some(worker) if errors.is_empty() => Ok((layers, worker)),
+            None if errors.is_empty() => Err(SpecError {
+                errors: vec!["no layers".to_string()],
+            }),
+            _ => Err(SpecError { errors }),
+        }
+    }
+}
+
+impl<S> Default for WebhookLayers<S>
+where
+    S: Subscriber + for<'a> LookupSpan<'a>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The problems found in a `WebhookLayersSpec`, each prefixed by the index of the layer, e.g.
+/// `layers[1]: unknown kind "teams"`.
+#[derive(Debug)]
+pub struct SpecError {
+    pub errors: Vec<String>,
+}
+
+impl fmt::Display for SpecError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid webhook layers spec: {}", self.errors.join("; "))
+    }
+}
+
+impl std::error::Error for SpecError {}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    use crate::spec::{WebhookLayers, WebhookLayersSpec};
+    use crate::testing::MockWebhook;
+    use crate::{Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+    struct Endpoint(String);
+
+    impl Config for Endpoint {
+        fn webhook_url(&self) -> &str {
+            &self.0
+        }
+
+        fn new_from_env() -> Self {
+            Endpoint("https://example.com/webhook".to_string())
+        }
+    }
+
+    #[derive(Debug)]
+    struct Text(String, String);
+
+    impl WebhookMessage for Text {
+        fn webhook_url(&self) -> &str {
+            &self.0
+        }
+
+        fn serialize(&self) -> String {
+            self.1.clone()
+        }
+    }
+
+    struct TextFactory;
+
+    impl WebhookMessageFactory for TextFactory {
+        fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+            Text(inputs.webhook_url, inputs.message)
+        }
+
+        fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage {
+            let webhook_url = inputs[0].webhook_url.clone();
+            Text(webhook_url, inputs.into_iter().map(|inputs| inputs.message).collect::<Vec<_>>().join("\n"))
+        }
+    }
+
+    #[tokio::test]
+    async fn every_layer_of_the_spec_sends_its_messages_through_the_shared_worker() {
+        let (db, errors) = (MockWebhook::start().await, MockWebhook::start().await);
+        let spec: WebhookLayersSpec = serde_json::from_value(json!({
+            "layers": [
+                { "kind": "text", "app_name": "app", "webhook_url": db.url(), "target_include": "^app::db" },
+                { "kind": "text", "app_name": "app", "webhook_url": errors.url(), "level": "error" },
+            ]
+        }))
+        .unwrap();
+        let (layers, worker) = WebhookLayers::<Registry>::new()
+            .kind::<Endpoint, TextFactory>("text", Endpoint)
+            .from_spec(spec)
+            .unwrap();
+        worker.start().await;
+        tracing::subscriber::with_default(Registry::default().with(layers), || {
+            tracing::warn!(target: "app::db::pool", "pool exhausted");
+            tracing::error!(target: "app::http", "request failed");
+            tracing::warn!(target: "app::http", "slow request");
+        });
+        worker.clone().shutdown().await;
+        let bodies = |webhook: &MockWebhook| -> Vec<String> {
+            webhook.requests().into_iter().map(|request| request.body).collect()
+        };
+        assert_eq!(bodies(&db), ["pool exhausted"]);
+        assert_eq!(bodies(&errors), ["request failed"]);
+        assert_eq!((worker.metrics().enqueued, worker.metrics().sent), (2, 2));
+    }
+
+    #[test]
+    fn every_problem_of_the_spec_is_reported() {
+        let spec: WebhookLayersSpec = serde_json::from_value(json!({
+            "layers": [
+                { "kind": "text", "app_name": "app", "webhook_url": "https://example.com/a", "message_include": "(" },
+                { "kind": "teams", "app_name": "app", "webhook_url": "https://example.com/b" },
+                { "kind": "text", "app_name": "app", "webhook_url": "https://example.com/c", "level": "loud" },
+            ]
+        }))
+        .unwrap();
+        let error = WebhookLayers::<Registry>::new()
+            .kind::<Endpoint, TextFactory>("text", Endpoint)
+            .from_spec(spec)
+            .err()
+            .unwrap();
+        assert_eq!(error.errors.len(), 3);
+        assert!(error.errors[0].starts_with("layers[0]: invalid regular expression in message_include: "));
+        assert_eq!(error.errors[1], r#"layers[1]: unknown kind "teams""#);
+        assert_eq!(error.errors[2], r#"layers[2]: invalid level: "loud""#);
+
+        let error = WebhookLayers::<Registry>::new().from_spec(WebhookLayersSpec::default()).err().unwrap();
+        assert_eq!(error.errors, ["no layers"]);
+    }
+}
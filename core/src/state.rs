@@ -0,0 +1,107 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt::Debug;
+use std::sync::Mutex;
+
+/// Storage for the state kept between events, such as the threads of spans, or the occurrences
+/// counted by the `DedupFilter` and `RateLimitFilter`, keyed by strings.
+///
+/// The default, `MemoryStateStore`, keeps it in the process. Several instances of a service can
+/// share it instead, e.g. in Redis, so that duplicates are dropped and occurrences limited across all
+/// of them rather than per instance, the filters keying their state by a hash stable across
+/// processes and builds. Spans only exist within a process, so their threads are kept apart per
+/// process even in a shared store, which then only spares the memory of the process.
+pub trait StateStore: Debug + Send + Sync {
+    /// The value of the key, if it is set.
+    fn get(&self, key: &str) -> Option<String>;
+
+    /// Set the value of the key, replacing any previous value.
+    fn set(&self, key: &str, value: String);
+
+    /// Add `by` to the integer value of the key, counting from 0 if it is not set or not an integer,
+    /// and return the new value.
+    fn incr(&self, key: &str, by: i64) -> i64;
+}
+
+/// A `StateStore` keeping values in memory, up to `max_keys` of them, the oldest being forgotten
+/// first.
+#[derive(Debug)]
+pub struct MemoryStateStore {
+    max_keys: usize,
+    state: Mutex<MemoryState>,
+}
+
+#[derive(Debug, Default)]
+struct MemoryState {
+    values: HashMap<String, String>,
+    /// The keys, in the order they were first set.
+    order: VecDeque<String>,
+}
+
+impl MemoryStateStore {
+    /// Create a store holding up to `max_keys` values.
+    pub fn new(max_keys: usize) -> Self {
+        Self {
+            max_keys: max_keys.max(1),
+            state: Mutex::default(),
+        }
+    }
+}
+
+impl Default for MemoryStateStore {
+    /// A store holding up to 1024 values.
+    fn default() -> Self {
+        Self::new(1024)
+    }
+}
+
+impl MemoryState {
+    fn insert(&mut self, key: &str, value: String, max_keys: usize) {
+        if !self.values.contains_key(key) {
+            if self.order.len() >= max_keys {
+                if let Some(oldest) = self.order.pop_front() {
+                    self.values.remove(&oldest);
+                }
+            }
+            self.order.push_back(key.to_string());
+        }
+        self.values.insert(key.to_string(), value);
+    }
+}
+
+impl StateStore for MemoryStateStore {
+    fn get(&self, key: &str) -> Option<String> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).values.get(key).cloned()
+    }
+
+    fn set(&self, key: &str, value: String) {
+        self.state
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .insert(key, value, self.max_keys);
+    }
+
+    fn incr(&self, key: &str, by: i64) -> i64 {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        let value = state.values.get(key).and_then(|value| value.parse::<i64>().ok()).unwrap_or(0) + by;
+        state.insert(key, value.to_string(), self.max_keys);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::state::{MemoryStateStore, StateStore};
+
+    #[test]
+    fn memory_stores_forget_the_oldest_keys_first() {
+        let store = MemoryStateStore::new(2);
+        store.set("a", "1".to_string());
+        assert_eq!(store.incr("a", 2), 3);
+        assert_eq!(store.incr("b", -1), -1);
+        assert_eq!(store.get("a").as_deref(), Some("3"));
+        store.set("c", "x".to_string());
+        assert_eq!(store.get("a"), None);
+        assert_eq!(store.get("b").as_deref(), Some("-1"));
+        assert_eq!(store.incr("c", 1), 1);
+    }
+}
@@ -0,0 +1,216 @@
+use syslog::{Formatter3164, Logger, LoggerBackend};
+use tracing::Level;
+
+pub use syslog::Facility;
+
+use crate::layer::BuildError;
+use crate::WebhookMessage;
+
+/// How to reach the syslog daemon.
+#[derive(Clone, Debug)]
+pub enum SyslogTransport {
+    /// The local Unix socket (e.g. `/dev/log`). On systemd hosts, this socket is served by
+    /// journald, so messages end up in the journal.
+    Unix,
+    /// A remote syslog server over UDP, sending from the `local` address.
+    Udp { local: String, server: String },
+    /// A remote syslog server over TCP.
+    Tcp { server: String },
+}
+
+/// Configuration describing how to write tracing events to syslog instead of a webhook.
+///
+/// The payload produced by the message factory is written as-is, and the level of the event is
+/// used as the syslog severity.
+#[derive(Clone, Debug)]
+pub struct SyslogConfig {
+    pub(crate) facility: Facility,
+    pub(crate) transport: SyslogTransport,
+}
+
+impl SyslogConfig {
+    pub fn new(facility: Facility, transport: SyslogTransport) -> Self {
+        Self { facility, transport }
+    }
+
+    /// Create a new config for writing messages to syslog using configuration available in the
+    /// environment.
+    ///
+    /// Optional env vars:
+    ///   * SYSLOG_FACILITY: the facility name, e.g. `user` or `local0` (default: `user`)
+    ///   * SYSLOG_SERVER: the `host:port` of a remote syslog server to send to over UDP (default:
+    ///     the local Unix socket)
+    pub fn new_from_env() -> Self {
+        Self::try_new_from_env().expect("valid syslog facility in env")
+    }
+
+    /// Like `new_from_env`, but returns an error naming the invalid variable rather than panicking.
+    pub fn try_new_from_env() -> Result<Self, BuildError> {
+        let facility = match std::env::var("SYSLOG_FACILITY") {
+            Ok(facility) => facility.parse().map_err(|_| BuildError::InvalidEnvVar {
+                var: "SYSLOG_FACILITY",
+                value: facility,
+            })?,
+            Err(_) => Facility::default(),
+        };
+        let transport = match std::env::var("SYSLOG_SERVER") {
+            Ok(server) => SyslogTransport::Udp {
+                local: "0.0.0.0:0".to_string(),
+                server,
+            },
+            Err(_) => SyslogTransport::Unix,
+        };
+        Ok(Self::new(facility, transport))
+    }
+
+    fn connect(&self) -> syslog::Result<Logger<LoggerBackend, Formatter3164>> {
+        let formatter = Formatter3164 {
+            facility: self.facility,
+            ..Default::default()
+        };
+        match &self.transport {
+            SyslogTransport::Unix => syslog::unix(formatter),
+            SyslogTransport::Udp { local, server } => syslog::udp(formatter, local.as_str(), server.as_str()),
+            SyslogTransport::Tcp { server } => syslog::tcp(formatter, server.as_str()),
+        }
+    }
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self::new_from_env()
+    }
+}
+
+type SyslogLogger = Logger<LoggerBackend, Formatter3164>;
+
+/// Writes messages to syslog, (re)connecting lazily whenever the connection is missing or broken.
+pub(crate) struct SyslogWriter {
+    config: SyslogConfig,
+    logger: Option<SyslogLogger>,
+}
+
+impl SyslogWriter {
+    pub(crate) fn new(config: SyslogConfig) -> Self {
+        Self { config, logger: None }
+    }
+
+    /// Writes a single message to syslog. Returns whether the message was written.
+    ///
+    /// Connecting and writing block on the socket, e.g. for as long as the TCP connection to a
+    /// remote server takes, so they are done on a blocking thread rather than on the worker's.
+    pub(crate) async fn write(&mut self, payload: &dyn WebhookMessage, message: String) -> bool {
+        let config = self.config.clone();
+        let logger = self.logger.take();
+        let level = payload.level();
+        match tokio::task::spawn_blocking(move || write(&config, logger, level, message)).await {
+            Ok((logger, written)) => {
+                self.logger = logger;
+                written
+            }
+            Err(e) => {
+                #[cfg(feature = "log-errors")]
+                eprintln!("ERROR: failed to write message to syslog: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Writes a single message to syslog, connecting first if there is no logger. Returns the logger
+/// to reuse for the next message, if it is still connected, and whether the message was written.
+fn write(
+    config: &SyslogConfig,
+    logger: Option<SyslogLogger>,
+    level: Option<Level>,
+    message: String,
+) -> (Option<SyslogLogger>, bool) {
+    let mut logger = match logger.map_or_else(|| config.connect(), Ok) {
+        Ok(logger) => logger,
+        Err(e) => {
+            #[cfg(feature = "log-errors")]
+            eprintln!("ERROR: failed to connect to syslog: {}", e);
+            return (None, false);
+        }
+    };
+    let result = match level {
+        Some(Level::ERROR) => logger.err(message),
+        Some(Level::WARN) => logger.warning(message),
+        Some(Level::INFO) => logger.info(message),
+        Some(Level::DEBUG) | Some(Level::TRACE) => logger.debug(message),
+        None => logger.notice(message),
+    };
+    match result {
+        Ok(()) => (Some(logger), true),
+        Err(e) => {
+            #[cfg(feature = "log-errors")]
+            eprintln!("ERROR: failed to write message to syslog: {}", e);
+            // Reconnect on the next message, in case the daemon was restarted.
+            (None, false)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+
+    use tracing::Level;
+
+    use crate::layer::BuildError;
+    use crate::syslog::{Facility, SyslogConfig, SyslogTransport, SyslogWriter};
+    use crate::WebhookMessage;
+
+    #[derive(Debug)]
+    struct Text(&'static str);
+
+    impl WebhookMessage for Text {
+        fn webhook_url(&self) -> &str {
+            "https://example.com/webhook"
+        }
+
+        fn serialize(&self) -> String {
+            self.0.to_string()
+        }
+
+        fn level(&self) -> Option<Level> {
+            Some(Level::WARN)
+        }
+    }
+
+    #[tokio::test]
+    async fn messages_are_written_with_the_severity_of_their_level() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let config = SyslogConfig::new(
+            Facility::LOG_LOCAL0,
+            SyslogTransport::Udp {
+                local: "127.0.0.1:0".to_string(),
+                server: server.local_addr().unwrap().to_string(),
+            },
+        );
+        let mut writer = SyslogWriter::new(config);
+        assert!(writer.write(&Text("disk almost full"), "disk almost full".to_string()).await);
+        assert!(writer.logger.is_some());
+
+        let mut datagram = [0; 1024];
+        let len = server.recv(&mut datagram).unwrap();
+        let datagram = String::from_utf8_lossy(&datagram[..len]);
+        // local0 is facility 16, and warning is severity 4: 16 * 8 + 4.
+        assert!(datagram.starts_with("<132>"), "{}", datagram);
+        assert!(datagram.ends_with("disk almost full"), "{}", datagram);
+    }
+
+    #[test]
+    fn invalid_facilities_in_the_environment_are_reported() {
+        std::env::set_var("SYSLOG_FACILITY", "local9");
+        let result = SyslogConfig::try_new_from_env();
+        std::env::set_var("SYSLOG_FACILITY", "local1");
+        let config = SyslogConfig::try_new_from_env().unwrap();
+        std::env::remove_var("SYSLOG_FACILITY");
+        assert!(matches!(
+            result,
+            Err(BuildError::InvalidEnvVar { var: "SYSLOG_FACILITY", value }) if value == "local9"
+        ));
+        assert!(matches!(config.facility, Facility::LOG_LOCAL1));
+    }
+}
@@ -0,0 +1,332 @@
+use std::sync::Arc;
+
+use serde_json::{Map, Value};
+use tracing::Level;
+
+use crate::layer::{BuildError, WebhookLayer, WebhookLayerBuilder};
+use crate::{Config, EventFilters, HeaderMap, Presentation, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+/// Formats events as the JSON body of a `TemplateConfig`'s template, for endpoints shaped neither
+/// like Slack nor like Discord, without implementing a `WebhookMessageFactory`.
+///
+/// A batch of events is rendered as its first event, with the messages of every event on separate
+/// lines and the level of the most severe one.
+pub struct TemplateFactory;
+
+impl TemplateFactory {
+    pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<TemplateConfig, Self> {
+        WebhookLayer::builder(app_name, target_filters)
+    }
+}
+
+impl WebhookMessageFactory for TemplateFactory {
+    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+        TemplateMessage::new(inputs)
+    }
+
+    fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage {
+        // Levels compare as more verbose being greater, so ERROR is the lowest.
+        let level = inputs.iter().map(|inputs| inputs.event_level).min().unwrap_or(Level::ERROR);
+        let message = inputs.iter().map(|inputs| inputs.message.as_str()).collect::<Vec<_>>().join("\n");
+        let mut first = inputs.into_iter().next().expect("batches have at least one event");
+        first.event_level = level;
+        first.message = message;
+        TemplateMessage::new(first)
+    }
+}
+
+/// The values of the placeholders of a template for an event, serialized by rendering the template
+/// of the `TemplateConfig` given with `Presentation::template`, or as a JSON object without one.
+#[derive(Debug)]
+struct TemplateMessage {
+    webhook_url: String,
+    level: Level,
+    template: Option<Arc<Template>>,
+    values: Map<String, Value>,
+}
+
+impl TemplateMessage {
+    fn new(inputs: WebhookMessageInputs) -> Self {
+        let metadata = serde_json::from_str(&inputs.metadata).unwrap_or(Value::String(inputs.metadata));
+        let values = serde_json::json!({
+            "app_name": inputs.app_name,
+            "message": inputs.message,
+            "level": inputs.event_level.to_string(),
+            "target": inputs.target,
+            "span": inputs.span,
+            "source": format!("{}#L{}", inputs.source_file, inputs.source_line),
+            "metadata": metadata,
+            "error_sources": inputs.error_sources,
+            "sequence": inputs.sequence,
+            "timestamp": inputs.timestamp,
+        });
+        let values = match values {
+            Value::Object(values) => values,
+            _ => unreachable!("the values are an object"),
+        };
+        Self {
+            webhook_url: inputs.webhook_url,
+            level: inputs.event_level,
+            template: inputs.presentation.template,
+            values,
+        }
+    }
+}
+
+impl WebhookMessage for TemplateMessage {
+    fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    fn serialize(&self) -> String {
+        match &self.template {
+            Some(template) => template.render(&self.values),
+            None => Value::Object(self.values.clone()).to_string(),
+        }
+    }
+
+    fn level(&self) -> Option<Level> {
+        Some(self.level)
+    }
+
+    /// Appended to the value of `{{message}}`.
+    fn append_text(&mut self, text: &str) {
+        if let Some(Value::String(message)) = self.values.get_mut("message") {
+            message.push('\n');
+            message.push_str(text);
+        }
+    }
+}
+
+/// Configuration describing how to forward tracing events to an arbitrary endpoint, as the JSON body
+/// of a template, e.g. `{ "title": "{{target}}", "body": "{{message}}", "severity": "{{level}}" }`.
+///
+/// The placeholders are `{{message}}`, `{{level}}`, `{{target}}`, `{{metadata}}` (the fields of the
+/// event), `{{app_name}}`, `{{span}}`, `{{source}}`, `{{error_sources}}`, `{{sequence}}`, and
+/// `{{timestamp}}`. Within a string, a placeholder is replaced by its value as text, escaped for
+/// JSON; elsewhere, by its value as JSON, e.g. `"fields": {{metadata}}` for the fields as an
+/// object. Unknown or missing values are left empty within strings, and are `null` elsewhere.
+#[derive(Debug, Clone)]
+pub struct TemplateConfig {
+    pub(crate) webhook_url: String,
+    pub(crate) template: Arc<Template>,
+    pub(crate) headers: HeaderMap,
+}
+
+impl TemplateConfig {
+    /// Create a config rendering the given template, failing if the template does not render as
+    /// valid JSON.
+    pub fn new(webhook_url: String, template: String) -> Result<Self, serde_json::Error> {
+        let template = Template::parse(&template);
+        serde_json::from_str::<Value>(&template.render(&Map::new()))?;
+        Ok(Self {
+            webhook_url,
+            template: Arc::new(template),
+            headers: HeaderMap::new(),
+        })
+    }
+
+    /// Add headers to every request, e.g. an `Authorization` header for an internal tool.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Create a new config for forwarding messages to an endpoint using configuration available in
+    /// the environment.
+    ///
+    /// Required env vars:
+    ///   * WEBHOOK_URL
+    ///   * WEBHOOK_TEMPLATE
+    pub fn new_from_env() -> Self {
+        Self::try_new_from_env().expect("webhook url and valid template in env")
+    }
+
+    /// Like `new_from_env`, but returns an error naming the missing or invalid variable rather than
+    /// panicking.
+    pub fn try_new_from_env() -> Result<Self, BuildError> {
+        let webhook_url = std::env::var("WEBHOOK_URL").map_err(|_| BuildError::MissingEnvVar { var: "WEBHOOK_URL" })?;
+        let template =
+            std::env::var("WEBHOOK_TEMPLATE").map_err(|_| BuildError::MissingEnvVar { var: "WEBHOOK_TEMPLATE" })?;
+        Self::new(webhook_url, template.clone()).map_err(|_| BuildError::InvalidEnvVar {
+            var: "WEBHOOK_TEMPLATE",
+            value: template,
+        })
+    }
+}
+
+impl Config for TemplateConfig {
+    fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    fn presentation(&self, _level: Level) -> Presentation {
+        Presentation {
+            template: Some(self.template.clone()),
+            ..Presentation::default()
+        }
+    }
+
+    fn new_from_env() -> Self
+    where
+        Self: Sized,
+    {
+        Self::new_from_env()
+    }
+
+    fn try_new_from_env() -> Result<Self, BuildError>
+    where
+        Self: Sized,
+    {
+        Self::try_new_from_env()
+    }
+}
+
+/// A template of a `TemplateConfig`, parsed into its text and its `{{placeholders}}`.
+#[derive(Debug, PartialEq)]
+pub struct Template {
+    parts: Vec<TemplatePart>,
+}
+
+#[derive(Debug, PartialEq)]
+enum TemplatePart {
+    Text(String),
+    /// The name of a placeholder, and whether it is within a JSON string.
+    Placeholder { name: String, in_string: bool },
+}
+
+impl Template {
+    fn parse(template: &str) -> Self {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut in_string = false;
+        let mut escaped = false;
+        let mut rest = template;
+        while let Some(c) = rest.chars().next() {
+            if let (true, Some(end)) = (rest.starts_with("{{"), rest.find("}}")) {
+                parts.push(TemplatePart::Text(std::mem::take(&mut text)));
+                parts.push(TemplatePart::Placeholder {
+                    name: rest[2..end].trim().to_string(),
+                    in_string,
+                });
+                rest = &rest[end + 2..];
+                continue;
+            }
+            if escaped {
+                escaped = false;
+            } else if in_string && c == '\\' {
+                escaped = true;
+            } else if c == '"' {
+                in_string = !in_string;
+            }
+            text.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+        parts.push(TemplatePart::Text(text));
+        Self { parts }
+    }
+
+    /// The template with its placeholders replaced by the given values.
+    fn render(&self, values: &Map<String, Value>) -> String {
+        let mut rendered = String::new();
+        for part in &self.parts {
+            match part {
+                TemplatePart::Text(text) => rendered.push_str(text),
+                TemplatePart::Placeholder { name, in_string } => {
+                    let value = values.get(name).unwrap_or(&Value::Null);
+                    if *in_string {
+                        rendered.push_str(&string_contents(value));
+                    } else {
+                        rendered.push_str(&value.to_string());
+                    }
+                }
+            }
+        }
+        rendered
+    }
+}
+
+/// A value as text, escaped to be written within a JSON string.
+fn string_contents(value: &Value) -> String {
+    let text = match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        value => value.to_string(),
+    };
+    let quoted = Value::String(text).to_string();
+    quoted[1..quoted.len() - 1].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use tracing::Level;
+
+    use crate::layer::BuildError;
+    use crate::template::{TemplateConfig, TemplateFactory};
+    use crate::testing;
+    use crate::{Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+    fn inputs() -> WebhookMessageInputs {
+        WebhookMessageInputs {
+            message: "disk \"almost\" full".to_string(),
+            target: "app::disk".to_string(),
+            metadata: json!({ "free_mb": 12 }).to_string(),
+            source_line: 7,
+            source_file: "src/disk.rs".to_string(),
+            ..testing::inputs()
+        }
+    }
+
+    #[test]
+    fn templates_are_rendered_as_valid_json() {
+        let template = r#"{"title": "[{{level}}] {{target}}", "body": "{{message}} at {{timestamp}}",
+            "severity": "{{ level }}", "fields": {{metadata}}, "sequence": {{sequence}}, "extra": "{{nope}}"}"#;
+        let config = TemplateConfig::new("https://example.com/alerts".to_string(), template.to_string()).unwrap();
+        let message = TemplateFactory::create(WebhookMessageInputs {
+            presentation: config.presentation(Level::WARN),
+            ..inputs()
+        });
+        let body: Value = serde_json::from_str(&message.serialize()).unwrap();
+        assert_eq!(
+            body,
+            json!({
+                "title": "[WARN] app::disk",
+                "body": "disk \"almost\" full at ",
+                "severity": "WARN",
+                "fields": { "free_mb": 12 },
+                "sequence": null,
+                "extra": "",
+            })
+        );
+
+        assert!(TemplateConfig::new(String::new(), r#"{"body": {{message}"#.to_string()).is_err());
+    }
+
+    #[test]
+    fn invalid_templates_in_the_environment_are_reported() {
+        std::env::set_var("WEBHOOK_URL", "https://example.com/alerts");
+        std::env::set_var("WEBHOOK_TEMPLATE", r#"{"body": {{message}"#);
+        let result = TemplateConfig::try_new_from_env();
+        std::env::set_var("WEBHOOK_TEMPLATE", r#"{"body": "{{message}}"}"#);
+        let config = TemplateConfig::try_new_from_env().unwrap();
+        std::env::remove_var("WEBHOOK_URL");
+        std::env::remove_var("WEBHOOK_TEMPLATE");
+        assert!(matches!(
+            result,
+            Err(BuildError::InvalidEnvVar { var: "WEBHOOK_TEMPLATE", value }) if value == r#"{"body": {{message}"#
+        ));
+
+        let mut message = TemplateFactory::create(WebhookMessageInputs {
+            presentation: config.presentation(Level::WARN),
+            ..inputs()
+        });
+        message.append_text("(truncated)");
+        assert_eq!(message.serialize(), r#"{"body": "disk \"almost\" full\n(truncated)"}"#);
+    }
+}
@@ -0,0 +1,186 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::StatusCode;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use tracing::Level;
+
+use crate::{Presentation, WebhookMessageInputs};
+
+/// A request received by a `MockWebhook`.
+#[derive(Debug, Clone)]
+pub struct ReceivedRequest {
+    /// The path of the request, with its query string, e.g. `/webhook?wait=true`.
+    pub path: String,
+    pub headers: HeaderMap,
+    pub body: String,
+}
+
+/// An HTTP server on localhost recording the requests it receives, and responding to them with
+/// `200 OK` unless told otherwise with `respond_with`, to test the messages a layer sends end to
+/// end, e.g. that the filters of an application let through the events it expects. It stops when
+/// dropped. Enabled with the `testing` feature.
+///
+/// ```rust,ignore
+/// let webhook = MockWebhook::start().await;
+/// let (layer, worker) = SlackLayer::builder("my-app".to_string(), target_filters)
+///     .config(SlackConfig::new(webhook.url()))
+///     .build()?;
+/// worker.start().await;
+/// tracing::subscriber::with_default(Registry::default().with(layer), || tracing::error!("disk full"));
+/// worker.shutdown().await;
+/// assert!(webhook.requests()[0].body.contains("disk full"));
+/// ```
+#[derive(Debug)]
+pub struct MockWebhook {
+    url: String,
+    state: Arc<Mutex<MockState>>,
+    handle: JoinHandle<()>,
+}
+
+#[derive(Debug, Default)]
+struct MockState {
+    requests: Vec<ReceivedRequest>,
+    /// The responses to the next requests, in order.
+    responses: VecDeque<(StatusCode, HeaderMap, String)>,
+}
+
+impl MockWebhook {
+    /// Start a server on a free port of localhost.
+    pub async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("failed to bind mock webhook");
+        let url = format!("http://{}/webhook", listener.local_addr().expect("mock webhook address"));
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let handle = tokio::spawn({
+            let state = state.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    tokio::spawn(serve(stream, state.clone()));
+                }
+            }
+        });
+        Self { url, state, handle }
+    }
+
+    /// The URL to send messages to, at the path `/webhook`.
+    pub fn url(&self) -> String {
+        self.url.clone()
+    }
+
+    /// Respond to the next request not yet answered by an earlier call with the given status and
+    /// body, e.g. `500 Internal Server Error` to test retries.
+    pub fn respond_with(&self, status: StatusCode, body: &str) {
+        self.respond_with_headers(status, HeaderMap::new(), body);
+    }
+
+    /// Like `respond_with`, with additional headers, e.g. `Retry-After` to test rate limiting.
+    pub fn respond_with_headers(&self, status: StatusCode, headers: HeaderMap, body: &str) {
+        self.lock().responses.push_back((status, headers, body.to_string()));
+    }
+
+    /// The requests received so far, in the order they were received.
+    pub fn requests(&self) -> Vec<ReceivedRequest> {
+        self.lock().requests.clone()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, MockState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl Drop for MockWebhook {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Record the requests of a connection and respond to them, until it is closed.
+async fn serve(stream: TcpStream, state: Arc<Mutex<MockState>>) {
+    let mut stream = BufReader::new(stream);
+    while let Some(request) = read_request(&mut stream).await {
+        let (status, headers, body) = {
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            state.requests.push(request);
+            state.responses.pop_front().unwrap_or((StatusCode::OK, HeaderMap::new(), "ok".to_string()))
+        };
+        let headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{}: {}\r\n", name, value.to_str().unwrap_or_default()))
+            .collect();
+        let response = format!(
+            "HTTP/1.1 {} {}\r\ncontent-type: text/plain\r\n{}content-length: {}\r\n\r\n{}",
+            status.as_u16(),
+            status.canonical_reason().unwrap_or(""),
+            headers,
+            body.len(),
+            body
+        );
+        if stream.get_mut().write_all(response.as_bytes()).await.is_err() {
+            return;
+        }
+    }
+}
+
+/// Read an HTTP/1.1 request with a `content-length`, as sent by the worker, or `None` once the
+/// connection is closed.
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Option<ReceivedRequest> {
+    let mut line = String::new();
+    stream.read_line(&mut line).await.ok()?;
+    let path = line.split(' ').nth(1)?.to_string();
+    let mut headers = HeaderMap::new();
+    loop {
+        line.clear();
+        stream.read_line(&mut line).await.ok()?;
+        let header = line.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        let (name, value) = header.split_once(':')?;
+        let name = HeaderName::from_bytes(name.trim().as_bytes()).ok()?;
+        headers.append(name, HeaderValue::from_str(value.trim()).ok()?);
+    }
+    let content_length = headers
+        .get("content-length")
+        .and_then(|value| value.to_str().ok()?.parse().ok())
+        .unwrap_or(0);
+    let mut body = vec![0; content_length];
+    stream.read_exact(&mut body).await.ok()?;
+    Some(ReceivedRequest {
+        path,
+        headers,
+        body: String::from_utf8_lossy(&body).into_owned(),
+    })
+}
+
+/// The inputs of a WARN event, "user created", with no fields, to test a `WebhookMessageFactory`.
+/// Tests set the inputs they depend on with the struct update syntax, so adding an input only
+/// changes this function:
+///
+/// ```rust,ignore
+/// let message = SlackLayer::create(WebhookMessageInputs {
+///     event_level: Level::ERROR,
+///     ..testing::inputs()
+/// });
+/// ```
+pub fn inputs() -> WebhookMessageInputs {
+    WebhookMessageInputs {
+        app_name: "test-app".to_string(),
+        webhook_url: "https://example.com/webhook".to_string(),
+        message: "user created".to_string(),
+        target: "app::users".to_string(),
+        span: String::new(),
+        metadata: "{}".to_string(),
+        source_line: 42,
+        source_file: "src/users.rs".to_string(),
+        event_level: Level::WARN,
+        error_sources: Vec::new(),
+        sequence: None,
+        notify: true,
+        presentation: Presentation::default(),
+        span_key: None,
+        timestamp: None,
+    }
+}
@@ -0,0 +1,79 @@
+use std::time::SystemTime;
+
+use time::format_description::well_known::Rfc3339;
+use time::format_description::OwnedFormatItem;
+use time::OffsetDateTime;
+pub use time::UtcOffset;
+
+/// How the time of events is rendered in messages, selected with `Config::timestamp_format`.
+///
+/// Events are timestamped when they are recorded, rather than when they are sent, so messages
+/// delayed by batching, backpressure, or retries still show when their event happened.
+#[derive(Debug, Clone)]
+pub struct TimestampFormat {
+    offset: UtcOffset,
+    format: Option<OwnedFormatItem>,
+}
+
+impl TimestampFormat {
+    /// RFC 3339 timestamps in UTC, e.g. `2024-05-01T12:34:56.789Z`.
+    pub fn rfc3339() -> Self {
+        Self {
+            offset: UtcOffset::UTC,
+            format: None,
+        }
+    }
+
+    /// Render timestamps at the given offset from UTC, e.g. `UtcOffset::from_hms(-5, 0, 0)`, or
+    /// `UtcOffset::current_local_offset()` with the `local-offset` feature of `time`.
+    pub fn with_offset(mut self, offset: UtcOffset) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Render timestamps with a format description of the `time` crate, e.g.
+    /// `[year]-[month]-[day] [hour]:[minute]:[second] [offset_hour sign:mandatory]`, instead of RFC
+    /// 3339.
+    pub fn with_format(mut self, format: &str) -> Result<Self, time::error::InvalidFormatDescription> {
+        self.format = Some(time::format_description::parse_owned::<2>(format)?);
+        Ok(self)
+    }
+
+    /// The given time, rendered in this format. Times the format cannot represent, e.g. beyond the
+    /// year 9999 for RFC 3339, are rendered as seconds since the Unix epoch instead.
+    pub fn format(&self, time: SystemTime) -> String {
+        let time = OffsetDateTime::from(time).to_offset(self.offset);
+        let formatted = match &self.format {
+            Some(format) => time.format(format),
+            None => time.format(&Rfc3339),
+        };
+        formatted.unwrap_or_else(|_| time.unix_timestamp().to_string())
+    }
+}
+
+impl Default for TimestampFormat {
+    fn default() -> Self {
+        Self::rfc3339()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::timestamp::{TimestampFormat, UtcOffset};
+
+    #[test]
+    fn timestamps_are_rendered_at_the_configured_offset() {
+        let time = UNIX_EPOCH + Duration::from_millis(1_714_566_896_789);
+        assert_eq!(TimestampFormat::rfc3339().format(time), "2024-05-01T12:34:56.789Z");
+
+        let offset = UtcOffset::from_hms(-5, 0, 0).unwrap();
+        let format = TimestampFormat::rfc3339().with_offset(offset);
+        assert_eq!(format.format(time), "2024-05-01T07:34:56.789-05:00");
+
+        let format = format.with_format("[year]-[month]-[day] [hour]:[minute]").unwrap();
+        assert_eq!(format.format(time), "2024-05-01 07:34");
+        assert!(TimestampFormat::rfc3339().with_format("[nonsense]").is_err());
+    }
+}
@@ -16,7 +16,8 @@ async fn main() {
     let messages_to_exclude = vec![Regex::new("the message we want to exclude").unwrap()];
     let (discord_layer, background_worker) = DiscordLayer::builder("test-app".to_string(), targets_to_filter)
         .message_filters((Vec::new(), messages_to_exclude).into())
-        .build();
+        .build()
+        .unwrap();
     let subscriber = Registry::default().with(discord_layer);
     tracing::subscriber::set_global_default(subscriber).unwrap();
     background_worker.start().await;
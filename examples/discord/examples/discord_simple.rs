@@ -1,4 +1,4 @@
-use tracing::{info, instrument, warn, warn_span};
+use tracing::{info, instrument, warn};
 use tracing_subscriber::{layer::SubscriberExt, Registry};
 
 use tracing_layer_discord::DiscordLayer;
@@ -30,7 +30,9 @@ pub async fn controller() {
 #[tokio::main]
 async fn main() {
     let formatting_layer = tracing_bunyan_formatter::BunyanFormattingLayer::new("tracing_demo".into(), std::io::stdout);
-    let (discord_layer, background_worker) = DiscordLayer::builder("test-app".to_string(), Default::default()).build();
+    let (discord_layer, background_worker) = DiscordLayer::builder("test-app".to_string(), Default::default())
+        .build()
+        .unwrap();
     let subscriber = Registry::default()
         .with(discord_layer)
         .with(tracing_bunyan_formatter::JsonStorageLayer)
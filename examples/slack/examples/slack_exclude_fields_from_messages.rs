@@ -24,7 +24,8 @@ async fn main() {
     ];
     let (slack_layer, background_worker) = SlackLayer::builder("test-app".to_string(), targets_to_filter)
         .field_exclusion_filters(fields_to_exclude)
-        .build();
+        .build()
+        .unwrap();
     let subscriber = Registry::default().with(slack_layer);
     tracing::subscriber::set_global_default(subscriber).unwrap();
     background_worker.start().await;
@@ -15,7 +15,8 @@ async fn main() {
     let targets_to_filter: EventFilters = Regex::new("exclude_messages_below_level").unwrap().into();
     let (slack_layer, background_worker) = SlackLayer::builder("test-app".to_string(), targets_to_filter)
         .level_filters("info".to_string())
-        .build();
+        .build()
+        .unwrap();
     let subscriber = Registry::default().with(slack_layer);
     tracing::subscriber::set_global_default(subscriber).unwrap();
     background_worker.start().await;
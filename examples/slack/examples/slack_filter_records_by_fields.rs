@@ -20,7 +20,8 @@ async fn main() {
     let event_fields_to_filter: EventFilters = Regex::new("password").unwrap().into();
     let (slack_layer, background_worker) = SlackLayer::builder("test-app".to_string(), targets_to_filter)
         .event_by_field_filters(event_fields_to_filter)
-        .build();
+        .build()
+        .unwrap();
     let subscriber = Registry::default().with(slack_layer);
     tracing::subscriber::set_global_default(subscriber).unwrap();
     background_worker.start().await;
@@ -0,0 +1,179 @@
+use serde_json::{json, Map, Value};
+use tracing::Level;
+use tracing_layer_core::layer::{WebhookLayer, WebhookLayerBuilder};
+use tracing_layer_core::{EventFilters, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+use crate::{DiscordConfig, DiscordMessagePayload, SUPPRESS_NOTIFICATIONS};
+
+/// The maximum number of characters of every embed of a message, combined.
+const MAX_TOTAL_CHARS: usize = 6000;
+/// The maximum number of embeds in a message.
+const MAX_EMBEDS: usize = 10;
+/// The maximum number of fields of an embed.
+const MAX_FIELDS: usize = 25;
+const MAX_TITLE_CHARS: usize = 256;
+const MAX_DESCRIPTION_CHARS: usize = 4096;
+const MAX_FIELD_NAME_CHARS: usize = 256;
+const MAX_FIELD_VALUE_CHARS: usize = 1024;
+const MAX_FOOTER_CHARS: usize = 2048;
+
+/// Formats events as Discord embeds: the target as the title, the message as the description, a
+/// color for the level of the event, and each metadata field as a field of the embed.
+///
+/// Select it with the `F` type parameter of the layer, e.g. with `DiscordEmbedFactory::builder`.
+/// Embeds are truncated to fit Discord's limits of 25 fields per embed and 6000 characters per
+/// message, so a large event is sent partially rather than rejected.
+pub struct DiscordEmbedFactory;
+
+impl DiscordEmbedFactory {
+    pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<DiscordConfig, Self> {
+        WebhookLayer::builder(app_name, target_filters)
+    }
+
+    /// The payload of a message with an embed per event, the last embed summarizing the events
+    /// beyond the 10 embeds Discord accepts.
+    fn payload(inputs: Vec<WebhookMessageInputs>) -> DiscordMessagePayload {
+        let total = inputs.len();
+        let notify = inputs.iter().any(|inputs| inputs.notify);
+        // Levels compare as more verbose being greater, so ERROR is the lowest.
+        let level = inputs.iter().map(|inputs| inputs.event_level).min().unwrap_or(Level::ERROR);
+        let webhook_url = inputs.first().map(|inputs| inputs.webhook_url.clone()).unwrap_or_default();
+        let mut budget = Budget(MAX_TOTAL_CHARS);
+        let mut embeds = Vec::new();
+        for (i, inputs) in inputs.into_iter().enumerate() {
+            if i == MAX_EMBEDS - 1 && total > MAX_EMBEDS {
+                let summary = budget.take(&format!("+{} more events", total - i), MAX_DESCRIPTION_CHARS);
+                embeds.push(json!({ "description": summary }));
+                break;
+            }
+            embeds.push(embed(inputs, &mut budget));
+        }
+        DiscordMessagePayload {
+            content: None,
+            embeds: Some(embeds),
+            flags: (!notify).then_some(SUPPRESS_NOTIFICATIONS),
+            webhook_url,
+            level,
+        }
+    }
+}
+
+impl WebhookMessageFactory for DiscordEmbedFactory {
+    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+        Self::payload(vec![inputs])
+    }
+
+    fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage {
+        Self::payload(inputs)
+    }
+}
+
+/// The color of the sidebar of an embed for an event of the given level.
+pub(crate) fn level_color(level: Level) -> u32 {
+    match level {
+        Level::TRACE | Level::DEBUG => 1752220,
+        Level::INFO => 5763719,
+        Level::WARN => 15105570,
+        Level::ERROR => 15548997,
+    }
+}
+
+/// The embed of a single event, using up to the remaining budget of characters.
+fn embed(inputs: WebhookMessageInputs, budget: &mut Budget) -> Value {
+    let title = budget.take(&inputs.target, MAX_TITLE_CHARS);
+    let description = budget.take(&inputs.message, MAX_DESCRIPTION_CHARS);
+    let footer = budget.take(&format!("{} - {}", inputs.app_name, inputs.event_level), MAX_FOOTER_CHARS);
+
+    let mut fields = vec![(
+        "Source".to_string(),
+        format!("{}#L{}", inputs.source_file, inputs.source_line),
+        true,
+    )];
+    if !inputs.span.is_empty() {
+        fields.push(("Span".to_string(), inputs.span, true));
+    }
+    if let Some(sequence) = inputs.sequence {
+        fields.push(("Sequence".to_string(), format!("#{}", sequence), true));
+    }
+    if let Some(timestamp) = inputs.timestamp {
+        fields.push(("Time".to_string(), timestamp, true));
+    }
+    if !inputs.error_sources.is_empty() {
+        let caused_by = inputs
+            .error_sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| format!("{}: {}", i, source))
+            .collect::<Vec<_>>()
+            .join("\n");
+        fields.push(("Caused by".to_string(), caused_by, false));
+    }
+    match serde_json::from_str::<Map<String, Value>>(&inputs.metadata) {
+        Ok(metadata) => fields.extend(metadata.into_iter().map(|(key, value)| {
+            let value = match value {
+                Value::String(s) => s,
+                value => value.to_string(),
+            };
+            (key, value, true)
+        })),
+        Err(_) => fields.push(("Metadata".to_string(), inputs.metadata, false)),
+    }
+
+    // Keep room for a last field stating how many fields were omitted.
+    let omitted = fields.len().saturating_sub(MAX_FIELDS);
+    let kept = if omitted > 0 { MAX_FIELDS - 1 } else { fields.len() };
+    let mut embed_fields = Vec::new();
+    for (name, value, inline) in fields.into_iter().take(kept) {
+        // Discord rejects fields with an empty name or value.
+        if name.is_empty() || value.is_empty() || budget.0 < 2 {
+            continue;
+        }
+        let name = budget.take(&name, MAX_FIELD_NAME_CHARS);
+        let value = budget.take(&value, MAX_FIELD_VALUE_CHARS);
+        if !value.is_empty() {
+            embed_fields.push(json!({ "name": name, "value": value, "inline": inline }));
+        }
+    }
+    if omitted > 0 && budget.0 >= 2 {
+        let value = budget.take(&format!("+{} more fields", omitted + 1), MAX_FIELD_VALUE_CHARS);
+        embed_fields.push(json!({ "name": budget.take("…", MAX_FIELD_NAME_CHARS), "value": value, "inline": false }));
+    }
+
+    let mut embed = json!({
+        "color": level_color(inputs.event_level),
+        "fields": embed_fields,
+    });
+    // Discord rejects empty texts, which only happen once the budget is exhausted.
+    if !title.is_empty() {
+        embed["title"] = json!(title);
+    }
+    if !description.is_empty() {
+        embed["description"] = json!(description);
+    }
+    if !footer.is_empty() {
+        embed["footer"] = json!({ "text": footer });
+    }
+    embed
+}
+
+/// The number of characters left for the embeds of a message.
+struct Budget(usize);
+
+impl Budget {
+    /// Take up to `max` characters of `text` out of the budget, ending it with an ellipsis if it is
+    /// truncated.
+    fn take(&mut self, text: &str, max: usize) -> String {
+        let max = max.min(self.0);
+        let text = if text.chars().count() <= max {
+            text.to_string()
+        } else if max == 0 {
+            String::new()
+        } else {
+            let mut truncated: String = text.chars().take(max - 1).collect();
+            truncated.push('…');
+            truncated
+        };
+        self.0 -= text.chars().count();
+        text
+    }
+}
@@ -1,12 +1,31 @@
 #![doc = include_str!("../README.md")]
 
+use std::sync::Arc;
+use std::time::Duration;
+
 use serde::Serialize;
 use serde_json::Value;
 pub use tracing_layer_core::filters::EventFilters;
 pub use tracing_layer_core::layer::WebhookLayer;
 use tracing_layer_core::layer::WebhookLayerBuilder;
 pub use tracing_layer_core::BackgroundWorker;
-use tracing_layer_core::{Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+use tracing::Level;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use tracing_layer_core::Identity;
+pub use tracing_layer_core::clock::{Clock, MockClock, SystemClock};
+pub use tracing_layer_core::BackoffJitter;
+pub use tracing_layer_core::BuildError;
+pub use tracing_layer_core::{HeaderMap, HeaderName, HeaderValue};
+pub use tracing_layer_core::timestamp::{TimestampFormat, UtcOffset};
+use tracing_layer_core::{Config, ResponseError, StatusCode, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs, DEFAULT_MAX_RETRIES, DEFAULT_REQUEST_TIMEOUT};
+#[cfg(feature = "syslog")]
+pub use tracing_layer_core::syslog::{Facility, SyslogConfig, SyslogTransport};
+#[cfg(feature = "testing")]
+pub use tracing_layer_core::testing::{MockWebhook, ReceivedRequest};
+
+pub use crate::embed::DiscordEmbedFactory;
+
+mod embed;
 
 pub struct DiscordLayer;
 
@@ -16,8 +35,9 @@ impl DiscordLayer {
     }
 }
 
-impl WebhookMessageFactory for DiscordLayer {
-    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+impl DiscordLayer {
+    /// The payload of the message for a single event.
+    fn payload(inputs: WebhookMessageInputs) -> DiscordMessagePayload {
         let target = inputs.target;
         let span = inputs.span;
         let metadata = inputs.metadata;
@@ -26,6 +46,10 @@ impl WebhookMessageFactory for DiscordLayer {
         let source_file = inputs.source_file;
         let source_line = inputs.source_line;
         let event_level = inputs.event_level;
+        let error_sources = inputs.error_sources;
+        let sequence = inputs.sequence;
+        let timestamp = inputs.timestamp;
+        let flags = (!inputs.notify).then_some(SUPPRESS_NOTIFICATIONS);
 
         #[cfg(feature = "embed")]
         {
@@ -36,13 +60,7 @@ impl WebhookMessageFactory for DiscordLayer {
                 tracing::Level::WARN => ":warning:",
                 tracing::Level::ERROR => ":x:",
             };
-            let event_level_color = match event_level {
-                tracing::Level::TRACE => 1752220,
-                tracing::Level::DEBUG => 1752220,
-                tracing::Level::INFO => 5763719,
-                tracing::Level::WARN => 15105570,
-                tracing::Level::ERROR => 15548997,
-            };
+            let event_level_color = embed::level_color(event_level);
 
             // Maximum characters allowed for a Discord field value
             const MAX_FIELD_VALUE_CHARS: usize = 1024 - 15;
@@ -95,6 +113,36 @@ impl WebhookMessageFactory for DiscordLayer {
                 }
             });
 
+            if let Some(sequence) = sequence {
+                discord_embed["fields"].as_array_mut().unwrap().push(serde_json::json!({
+                    "name": "Sequence",
+                    "value": format!("`#{}`", sequence),
+                    "inline": true
+                }));
+            }
+
+            if let Some(timestamp) = timestamp {
+                discord_embed["fields"].as_array_mut().unwrap().push(serde_json::json!({
+                    "name": "Time",
+                    "value": timestamp,
+                    "inline": true
+                }));
+            }
+
+            if !error_sources.is_empty() {
+                let caused_by = error_sources
+                    .iter()
+                    .enumerate()
+                    .map(|(i, source)| format!("{}: {}", i, source))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                discord_embed["fields"].as_array_mut().unwrap().push(serde_json::json!({
+                    "name": "Caused by",
+                    "value": format!("```\n{}\n```", caused_by.chars().take(MAX_FIELD_VALUE_CHARS).collect::<String>()),
+                    "inline": false
+                }));
+            }
+
             // Check if metadata exceeds the limit
             if metadata.len() <= MAX_FIELD_VALUE_CHARS {
                 // Metadata fits within a single field
@@ -128,18 +176,22 @@ impl WebhookMessageFactory for DiscordLayer {
             DiscordMessagePayload {
                 content: None,
                 embeds: Some(vec![discord_embed]),
+                flags,
                 webhook_url: inputs.webhook_url,
+                level: event_level,
             }
         }
         #[cfg(not(feature = "embed"))]
         {
-            let event_level = event.metadata().level().as_str();
-            let source_file = event.metadata().file().unwrap_or("Unknown");
-            let source_line = event.metadata().line().unwrap_or(0);
+            let caused_by: String = error_sources
+                .iter()
+                .enumerate()
+                .map(|(i, source)| format!("\n    {}: {}", i, source))
+                .collect();
             let payload = format!(
                 concat!(
                     "*Trace from {}*\n",
-                    "*Event [{}]*: \"{}\"\n",
+                    "*Event [{}]*: \"{}\"{}\n",
                     "*Target*: _{}_\n",
                     "*Span*: _{}_\n",
                     "*Metadata*:\n",
@@ -148,25 +200,183 @@ impl WebhookMessageFactory for DiscordLayer {
                     "```\n",
                     "*Source*: _{}#L{}_",
                 ),
-                app_name, event_level, message, span, target, metadata, source_file, source_line,
+                app_name, event_level.as_str(), message, caused_by, span, target, metadata, source_file, source_line,
             );
+            let payload = match sequence {
+                Some(sequence) => format!("{}\n*Sequence*: _#{}_", payload, sequence),
+                None => payload,
+            };
+            let payload = match timestamp {
+                Some(timestamp) => format!("{}\n*Time*: _{}_", payload, timestamp),
+                None => payload,
+            };
             DiscordMessagePayload {
                 content: Some(payload),
                 embeds: None,
+                flags,
                 webhook_url: inputs.webhook_url,
+                level: event_level,
             }
         }
     }
 }
 
+impl WebhookMessageFactory for DiscordLayer {
+    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+        Self::payload(inputs)
+    }
+
+    /// Combine the events into a single message, with an embed per event, or their texts separated
+    /// by a blank line without the `embed` feature.
+    ///
+    /// Discord rejects messages with more than 10 embeds, or whose embeds total more than 6000
+    /// characters, so batches should be kept small.
+    fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage {
+        let payloads: Vec<_> = inputs.into_iter().map(Self::payload).collect();
+        let content: Vec<_> = payloads.iter().filter_map(|payload| payload.content.clone()).collect();
+        let embeds: Vec<_> = payloads.iter().flat_map(|payload| payload.embeds.clone().unwrap_or_default()).collect();
+        DiscordMessagePayload {
+            content: (!content.is_empty()).then(|| content.join("\n\n")),
+            embeds: (!embeds.is_empty()).then_some(embeds),
+            // Notify if any of the events should notify.
+            flags: payloads.iter().map(|payload| payload.flags).min().flatten(),
+            webhook_url: payloads[0].webhook_url.clone(),
+            // Levels compare as more verbose being greater, so ERROR is the lowest.
+            level: payloads.iter().map(|payload| payload.level).min().unwrap_or(Level::ERROR),
+        }
+    }
+}
+
 /// Configuration describing how to forward tracing events to Discord.
 pub struct DiscordConfig {
     pub(crate) webhook_url: String,
+    pub(crate) webhook_urls: Vec<(String, u32)>,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub(crate) tls_identity: Option<Identity>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) routing_key: Option<String>,
+    pub(crate) routing_key_header: String,
+    pub(crate) max_retries: usize,
+    pub(crate) request_timeout: Duration,
+    pub(crate) retry_deadline: Option<Duration>,
+    pub(crate) backoff_jitter: BackoffJitter,
+    pub(crate) headers: HeaderMap,
+    pub(crate) pretty_metadata: bool,
+    pub(crate) timestamp_format: Option<TimestampFormat>,
+    pub(crate) dry_run: bool,
 }
 
 impl DiscordConfig {
     pub fn new(webhook_url: String) -> Self {
-        Self { webhook_url }
+        Self {
+            webhook_url,
+            webhook_urls: Vec::new(),
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            tls_identity: None,
+            clock: Arc::new(SystemClock),
+            routing_key: None,
+            routing_key_header: "X-Routing-Key".to_string(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            retry_deadline: None,
+            backoff_jitter: BackoffJitter::default(),
+            headers: HeaderMap::new(),
+            pretty_metadata: true,
+            timestamp_format: None,
+            dry_run: false,
+        }
+    }
+
+    /// Spread messages across several webhook URLs for the same channel, with their relative
+    /// weights, to raise the effective rate limit. A URL that is rate limited is skipped for a
+    /// few seconds.
+    pub fn with_webhook_urls(mut self, webhook_urls: Vec<(String, u32)>) -> Self {
+        self.webhook_urls = webhook_urls;
+        self
+    }
+
+    /// Present a client certificate to the webhook endpoint, for endpoints behind a gateway that
+    /// requires mutual TLS.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn with_tls_identity(mut self, identity: Identity) -> Self {
+        self.tls_identity = Some(identity);
+        self
+    }
+
+    /// The number of attempts at delivering each message before giving up on it, e.g. fewer for a
+    /// rate-limited channel or more for a flaky self-hosted endpoint. Defaults to 10.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// How long to wait for the webhook to respond to a request before retrying it. Defaults to 30
+    /// seconds.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// How long to keep attempting to deliver each message before giving up on it, even if it was
+    /// attempted fewer than `with_max_retries` times. Defaults to no limit.
+    pub fn with_retry_deadline(mut self, retry_deadline: Duration) -> Self {
+        self.retry_deadline = Some(retry_deadline);
+        self
+    }
+
+    /// How the delay between attempts at delivering a message is randomized. Defaults to
+    /// `BackoffJitter::None`.
+    pub fn with_backoff_jitter(mut self, backoff_jitter: BackoffJitter) -> Self {
+        self.backoff_jitter = backoff_jitter;
+        self
+    }
+
+    /// Send a key identifying this service or stream with every message, in the `X-Routing-Key`
+    /// header unless configured otherwise, for webhook receivers aggregating several sources.
+    pub fn with_routing_key(mut self, routing_key: String) -> Self {
+        self.routing_key = Some(routing_key);
+        self
+    }
+
+    /// The name of the header carrying the routing key.
+    pub fn with_routing_key_header(mut self, routing_key_header: String) -> Self {
+        self.routing_key_header = routing_key_header;
+        self
+    }
+
+    /// Add headers to every request, e.g. an `Authorization` header for an endpoint behind an
+    /// authenticating proxy.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Whether to send the fields of events as pretty-printed JSON, or as compact JSON leaving more
+    /// room for them under the length limits of messages. Defaults to `true`.
+    pub fn with_pretty_metadata(mut self, pretty_metadata: bool) -> Self {
+        self.pretty_metadata = pretty_metadata;
+        self
+    }
+
+    /// Show when each event was recorded in its message, rendered in the given format and time zone,
+    /// e.g. `TimestampFormat::rfc3339()`, rather than only when the destination received it, which
+    /// may be later when messages are queued or retried.
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = Some(timestamp_format);
+        self
+    }
+
+    /// Print the body of each message to stderr instead of sending it, e.g. to tune filters in
+    /// staging without posting to the channel. Defaults to `false`.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Use another source of the current time, e.g. a `MockClock` to test time-based features.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
     }
 
     /// Create a new config for forwarding messages to Discord using configuration
@@ -174,8 +384,26 @@ impl DiscordConfig {
     ///
     /// Required env vars:
     ///   * DISCORD_WEBHOOK_URL
+    ///
+    /// Optional env vars:
+    ///   * WEBHOOK_ROUTING_KEY
+    ///   * WEBHOOK_ROUTING_KEY_HEADER (default: `X-Routing-Key`)
+    ///   * DISCORD_DRY_RUN (`1` or `true` to print messages instead of sending them)
     pub fn new_from_env() -> Self {
-        Self::new(std::env::var("DISCORD_WEBHOOK_URL").expect("discord webhook url in env"))
+        Self::try_new_from_env().expect("discord webhook url in env")
+    }
+
+    /// Like `new_from_env`, but returns an error naming the missing variable rather than panicking.
+    pub fn try_new_from_env() -> Result<Self, BuildError> {
+        let webhook_url =
+            std::env::var("DISCORD_WEBHOOK_URL").map_err(|_| BuildError::MissingEnvVar { var: "DISCORD_WEBHOOK_URL" })?;
+        let mut config = Self::new(webhook_url);
+        config.routing_key = std::env::var("WEBHOOK_ROUTING_KEY").ok();
+        if let Ok(routing_key_header) = std::env::var("WEBHOOK_ROUTING_KEY_HEADER") {
+            config.routing_key_header = routing_key_header;
+        }
+        config.dry_run = matches!(std::env::var("DISCORD_DRY_RUN").as_deref(), Ok("1") | Ok("true"));
+        Ok(config)
     }
 }
 
@@ -190,24 +418,110 @@ impl Config for DiscordConfig {
         &self.webhook_url
     }
 
+    fn webhook_urls(&self) -> &[(String, u32)] {
+        &self.webhook_urls
+    }
+
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    fn tls_identity(&self) -> Option<Identity> {
+        self.tls_identity.clone()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    fn retry_deadline(&self) -> Option<Duration> {
+        self.retry_deadline
+    }
+
+    fn backoff_jitter(&self) -> BackoffJitter {
+        self.backoff_jitter
+    }
+
+    fn routing_key(&self) -> Option<&str> {
+        self.routing_key.as_deref()
+    }
+
+    fn routing_key_header(&self) -> &str {
+        &self.routing_key_header
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    fn pretty_metadata(&self) -> bool {
+        self.pretty_metadata
+    }
+
+    fn timestamp_format(&self) -> Option<&TimestampFormat> {
+        self.timestamp_format.as_ref()
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
+    fn response_error(&self, _status: StatusCode, body: &str) -> Option<ResponseError> {
+        // Discord's errors have a code and a message, while a posted message, returned with
+        // `?wait=true`, has an ID.
+        let response = serde_json::from_str::<Value>(body).ok()?;
+        let (code, message) = match (response["code"].as_u64(), response["message"].as_str()) {
+            (Some(code), Some(message)) if response.get("id").is_none() => (code, message),
+            _ => return None,
+        };
+        let error = format!("{} (code {})", message, code);
+        if code == DISCORD_OVERLOADED {
+            Some(ResponseError::Retryable(error))
+        } else {
+            Some(ResponseError::Permanent(error))
+        }
+    }
+
     fn new_from_env() -> Self
     where
         Self: Sized,
     {
         Self::new_from_env()
     }
+
+    fn try_new_from_env() -> Result<Self, BuildError>
+    where
+        Self: Sized,
+    {
+        Self::try_new_from_env()
+    }
 }
 
+/// The message flag sending a message without triggering push and desktop notifications.
+const SUPPRESS_NOTIFICATIONS: u64 = 1 << 12;
+
+/// The code of Discord's error for an API resource currently overloaded, worth retrying.
+const DISCORD_OVERLOADED: u64 = 130000;
+
 /// The message sent to Discord. The logged record being "drained" will be
 /// converted into this format.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub(crate) struct DiscordMessagePayload {
     #[serde(skip_serializing_if = "Option::is_none")]
     content: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     embeds: Option<Vec<Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flags: Option<u64>,
     #[serde(skip_serializing)]
     webhook_url: String,
+    #[serde(skip_serializing)]
+    level: Level,
 }
 
 impl WebhookMessage for DiscordMessagePayload {
@@ -218,7 +532,145 @@ impl WebhookMessage for DiscordMessagePayload {
     fn serialize(&self) -> String {
         serde_json::to_string(self).expect("failed to serialize discord message")
     }
+
+    fn level(&self) -> Option<Level> {
+        Some(self.level)
+    }
+
+    /// Appended to the content of the message, shown above its embeds.
+    fn append_text(&mut self, text: &str) {
+        self.content = Some(match self.content.take() {
+            Some(existing) => format!("{}\n{}", existing, text),
+            None => text.to_string(),
+        });
+    }
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use serde_json::{json, Value};
+    use tracing::Level;
+    use tracing_layer_core::{
+        testing, Config, ResponseError, StatusCode, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs,
+    };
+
+    use crate::{DiscordConfig, DiscordEmbedFactory, DiscordLayer};
+
+    fn inputs() -> WebhookMessageInputs {
+        WebhookMessageInputs {
+            webhook_url: "https://discord.com/api/webhooks/test".to_string(),
+            span: "create_user".to_string(),
+            event_level: Level::ERROR,
+            ..testing::inputs()
+        }
+    }
+
+    #[test]
+    fn discord_errors_in_successful_responses_are_failures() {
+        let config = DiscordConfig::new("https://discord.com/api/webhooks/test".to_string());
+        assert_eq!(config.response_error(StatusCode::NO_CONTENT, ""), None);
+        let posted = r#"{"id":"1","content":"hi","code":1,"message":"not an error"}"#;
+        assert_eq!(config.response_error(StatusCode::OK, posted), None);
+        let error = Some(ResponseError::Permanent("Invalid Form Body (code 50035)".to_string()));
+        assert_eq!(config.response_error(StatusCode::OK, r#"{"message":"Invalid Form Body","code":50035}"#), error);
+        let overloaded = r#"{"message":"API resource is currently overloaded.","code":130000}"#;
+        assert!(matches!(config.response_error(StatusCode::OK, overloaded), Some(ResponseError::Retryable(_))));
+    }
+
+    #[test]
+    fn message_is_sent_to_the_configured_webhook() {
+        let message = DiscordLayer::create(inputs());
+        assert_eq!(message.webhook_url(), "https://discord.com/api/webhooks/test");
+        assert_eq!(message.level(), Some(Level::ERROR));
+    }
+
+    #[cfg(feature = "embed")]
+    #[test]
+    fn message_serializes_to_an_embed() {
+        let payload: Value = serde_json::from_str(&DiscordLayer::create(inputs()).serialize()).unwrap();
+        assert_eq!(
+            payload,
+            json!({
+                "embeds": [{
+                    "title": "test-app - :x: ERROR",
+                    "description": "```rust\nuser created\n```",
+                    "fields": [
+                        { "name": "Target Span", "value": "`app::users::create_user`", "inline": true },
+                        { "name": "Source", "value": "`src/users.rs#L42`", "inline": true },
+                        { "name": "Metadata", "value": "```json\n{}\n```", "inline": false }
+                    ],
+                    "footer": { "text": "test-app" },
+                    "color": 15548997,
+                    "thumbnail": { "url": "https://example.com/error-thumbnail.png" }
+                }]
+            })
+        );
+    }
+
+    #[cfg(feature = "embed")]
+    #[test]
+    fn error_sources_are_rendered_as_a_caused_by_field() {
+        let mut inputs = inputs();
+        inputs.error_sources = vec!["connection reset".to_string(), "broken pipe".to_string()];
+        let payload: Value = serde_json::from_str(&DiscordLayer::create(inputs).serialize()).unwrap();
+        assert_eq!(
+            payload["embeds"][0]["fields"][2],
+            json!({
+                "name": "Caused by",
+                "value": "```\n0: connection reset\n1: broken pipe\n```",
+                "inline": false
+            })
+        );
+    }
+
+    #[cfg(feature = "embed")]
+    #[test]
+    fn sequence_number_is_rendered_as_a_field() {
+        let mut inputs = inputs();
+        inputs.sequence = Some(7);
+        let payload: Value = serde_json::from_str(&DiscordLayer::create(inputs).serialize()).unwrap();
+        assert_eq!(
+            payload["embeds"][0]["fields"][2],
+            json!({ "name": "Sequence", "value": "`#7`", "inline": true })
+        );
+    }
+
+    #[test]
+    fn notifications_are_suppressed_when_not_notifying() {
+        let mut inputs = inputs();
+        inputs.notify = false;
+        let payload: Value = serde_json::from_str(&DiscordLayer::create(inputs).serialize()).unwrap();
+        assert_eq!(payload["flags"], json!(4096));
+    }
+
+    #[cfg(feature = "embed")]
+    #[test]
+    fn batches_are_combined_into_an_embed_per_event() {
+        let mut second = inputs();
+        second.message = "user deleted".to_string();
+        second.event_level = Level::WARN;
+        second.notify = false;
+        let message = DiscordLayer::combine(vec![inputs(), second]);
+        assert_eq!(message.level(), Some(Level::ERROR));
+        let payload: Value = serde_json::from_str(&message.serialize()).unwrap();
+        assert_eq!(payload["embeds"][0]["description"], "```rust\nuser created\n```");
+        assert_eq!(payload["embeds"][1]["description"], "```rust\nuser deleted\n```");
+        assert_eq!(payload["flags"], Value::Null);
+    }
+
+    #[test]
+    fn embed_factory_truncates_to_discord_limits() {
+        let mut inputs = inputs();
+        inputs.message = "x".repeat(5000);
+        let metadata: serde_json::Map<_, _> = (0..30).map(|i| (format!("field_{:02}", i), json!(i))).collect();
+        inputs.metadata = Value::from(metadata).to_string();
+        let payload: Value = serde_json::from_str(&DiscordEmbedFactory::create(inputs).serialize()).unwrap();
+        let embed = &payload["embeds"][0];
+        assert_eq!(embed["title"], "app::users");
+        assert_eq!(embed["color"], 15548997);
+        assert_eq!(embed["description"].as_str().unwrap().chars().count(), 4096);
+        let fields = embed["fields"].as_array().unwrap();
+        assert_eq!(fields.len(), 25);
+        assert_eq!(fields[24]["value"], "+8 more fields");
+    }
+}
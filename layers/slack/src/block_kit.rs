@@ -0,0 +1,182 @@
+use serde_json::{json, Map, Value};
+use tracing::Level;
+use tracing_layer_core::layer::{WebhookLayer, WebhookLayerBuilder};
+use tracing_layer_core::{EventFilters, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+use crate::{common_span_key, mentions, SlackConfig, SlackMessagePayload};
+
+/// The maximum number of blocks in a message.
+const MAX_BLOCKS: usize = 50;
+/// The maximum number of fields of a section block.
+const MAX_SECTION_FIELDS: usize = 10;
+const MAX_HEADER_CHARS: usize = 150;
+const MAX_FIELD_CHARS: usize = 2000;
+const MAX_TEXT_CHARS: usize = 3000;
+
+/// Formats events with Slack's Block Kit: a header with the message, a section with the target and
+/// source location, and sections listing the event's fields, instead of a block of pretty-printed
+/// JSON.
+///
+/// Select it with the `F` type parameter of the layer, e.g. with `SlackBlockKitFactory::builder`.
+/// When a message would exceed Slack's limit of 50 blocks, each event falls back to a single block of
+/// text, and the message is split into several if it still exceeds it.
+pub struct SlackBlockKitFactory;
+
+impl SlackBlockKitFactory {
+    pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<SlackConfig, Self> {
+        WebhookLayer::builder(app_name, target_filters)
+    }
+
+    /// The payload of a message with the blocks of every event, separated by dividers.
+    fn payload(inputs: Vec<WebhookMessageInputs>) -> SlackMessagePayload {
+        let mut blocks = with_dividers(inputs.iter().map(event_blocks));
+        if blocks.len() > MAX_BLOCKS {
+            blocks = with_dividers(inputs.iter().map(|inputs| vec![text_block(inputs)]));
+        }
+        SlackMessagePayload {
+            // Shown in notifications, which do not render blocks, and notifying the mentions.
+            text: inputs.first().map(|first| {
+                let text = match mentions(inputs.iter().filter_map(|inputs| inputs.presentation.mention.as_deref())) {
+                    Some(mentions) => format!("{} {}", mentions, message(first)),
+                    None => message(first),
+                };
+                truncate(&text, MAX_TEXT_CHARS)
+            }),
+            blocks: Some(Value::from(blocks).to_string()),
+            attachments: None,
+            webhook_url: inputs.first().map(|inputs| inputs.webhook_url.clone()).unwrap_or_default(),
+            // Levels compare as more verbose being greater, so ERROR is the lowest.
+            level: inputs.iter().map(|inputs| inputs.event_level).min().unwrap_or(Level::ERROR),
+            span_key: common_span_key(inputs.iter().map(|inputs| inputs.span_key)),
+        }
+    }
+}
+
+impl WebhookMessageFactory for SlackBlockKitFactory {
+    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+        Self::payload(vec![inputs])
+    }
+
+    fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage {
+        Self::payload(inputs)
+    }
+}
+
+fn with_dividers(events: impl Iterator<Item = Vec<Value>>) -> Vec<Value> {
+    let mut blocks = Vec::new();
+    for event_blocks in events {
+        if !blocks.is_empty() {
+            blocks.push(json!({ "type": "divider" }));
+        }
+        blocks.extend(event_blocks);
+    }
+    blocks
+}
+
+/// The blocks of a single event.
+fn event_blocks(inputs: &WebhookMessageInputs) -> Vec<Value> {
+    let mut context = vec![json!({
+        "type": "mrkdwn",
+        "text": format!("{} {} *{}*", level_emoji(inputs.event_level), inputs.app_name, inputs.event_level),
+    })];
+    if let Some(sequence) = inputs.sequence {
+        context.push(json!({ "type": "mrkdwn", "text": format!("#{}", sequence) }));
+    }
+    if let Some(timestamp) = &inputs.timestamp {
+        context.push(json!({ "type": "mrkdwn", "text": timestamp }));
+    }
+    let mut location = vec![
+        field("Target", &inputs.target),
+        field("Source", &format!("{}#L{}", inputs.source_file, inputs.source_line)),
+    ];
+    if !inputs.span.is_empty() {
+        location.push(field("Span", &inputs.span));
+    }
+    let mut blocks = vec![
+        json!({
+            "type": "header",
+            "text": { "type": "plain_text", "text": truncate(&message(inputs), MAX_HEADER_CHARS), "emoji": true },
+        }),
+        json!({ "type": "context", "elements": context }),
+        json!({ "type": "section", "fields": location }),
+    ];
+    if !inputs.error_sources.is_empty() {
+        let caused_by = inputs
+            .error_sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| format!("{}: {}", i, source))
+            .collect::<Vec<_>>()
+            .join("\n");
+        blocks.push(section(&format!("*Caused by:*\n```\n{}\n```", caused_by)));
+    }
+    match serde_json::from_str::<Map<String, Value>>(&inputs.metadata) {
+        Ok(metadata) => {
+            let fields: Vec<_> = metadata
+                .iter()
+                .map(|(key, value)| match value {
+                    Value::String(s) => field(key, s),
+                    value => field(key, &value.to_string()),
+                })
+                .collect();
+            for fields in fields.chunks(MAX_SECTION_FIELDS) {
+                blocks.push(json!({ "type": "section", "fields": fields }));
+            }
+        }
+        Err(_) => blocks.push(section(&format!("```\n{}\n```", inputs.metadata))),
+    }
+    if let Some(raw_json) = &inputs.presentation.raw_json {
+        blocks.push(section(&format!("*Raw event:*\n```{}```", raw_json)));
+    }
+    blocks
+}
+
+/// A single block of text describing an event, used when its blocks do not fit in the message.
+fn text_block(inputs: &WebhookMessageInputs) -> Value {
+    section(&format!(
+        "{} *{}*: {}\n_{}_ at {}#L{}\n```\n{}\n```",
+        level_emoji(inputs.event_level),
+        inputs.event_level,
+        message(inputs),
+        inputs.target,
+        inputs.source_file,
+        inputs.source_line,
+        inputs.metadata,
+    ))
+}
+
+/// The message of an event, prefixed with the emoji of its level, if any.
+fn message(inputs: &WebhookMessageInputs) -> String {
+    match &inputs.presentation.emoji {
+        Some(emoji) => format!("{} {}", emoji, inputs.message),
+        None => inputs.message.clone(),
+    }
+}
+
+fn section(text: &str) -> Value {
+    json!({ "type": "section", "text": { "type": "mrkdwn", "text": truncate(text, MAX_TEXT_CHARS) } })
+}
+
+fn field(name: &str, value: &str) -> Value {
+    json!({ "type": "mrkdwn", "text": truncate(&format!("*{}*\n{}", name, value), MAX_FIELD_CHARS) })
+}
+
+fn level_emoji(level: Level) -> &'static str {
+    match level {
+        Level::TRACE => ":mag:",
+        Level::DEBUG => ":bug:",
+        Level::INFO => ":information_source:",
+        Level::WARN => ":warning:",
+        Level::ERROR => ":x:",
+    }
+}
+
+/// Truncate a text to at most `max` characters, ending it with an ellipsis if it is truncated.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
@@ -0,0 +1,54 @@
+use tracing::subscriber::SetGlobalDefaultError;
+use tracing_bunyan_formatter::JsonStorageLayer;
+use tracing_layer_core::{EventFilters, WorkerGuard};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::Registry;
+
+use crate::{SlackConfig, SlackLayer};
+
+/// Sets up forwarding events to Slack in one call: builds a `SlackLayer` with the given config,
+/// installs it as the global subscriber along with a `JsonStorageLayer` so the fields of spans are
+/// sent, and starts its background worker.
+///
+/// Keep the returned guard alive for as long as events should be sent, e.g. in `main`: the queued
+/// messages are delivered when it is shut down or dropped. Compose a `Registry` manually with
+/// `SlackLayer::builder` for anything more, e.g. other layers or filters.
+///
+/// Fails if a global subscriber was already set, in which case no worker is started.
+pub async fn init(
+    app_name: String,
+    target_filters: EventFilters,
+    config: SlackConfig,
+) -> Result<SlackGuard, SetGlobalDefaultError> {
+    // The layer has a config and no level filters, so it always builds.
+    let (slack_layer, background_worker) =
+        SlackLayer::builder(app_name, target_filters).config(config).build().expect("layer with a config");
+    let subscriber = Registry::default().with(JsonStorageLayer).with(slack_layer);
+    tracing::subscriber::set_global_default(subscriber)?;
+    Ok(background_worker.start_with_guard().await)
+}
+
+/// Shuts down the background worker started by `init` when dropped, after it delivered the queued
+/// messages.
+pub type SlackGuard = WorkerGuard;
+
+#[cfg(test)]
+mod tests {
+    use tracing_layer_core::testing::MockWebhook;
+    use tracing_layer_core::EventFilters;
+
+    use crate::init::init;
+    use crate::SlackConfig;
+
+    #[tokio::test]
+    async fn init_installs_the_layer_once_and_delivers_on_shutdown() {
+        let webhook = MockWebhook::start().await;
+        let config = || SlackConfig::new(webhook.url());
+        let guard = init("test-app".to_string(), EventFilters::default(), config()).await.unwrap();
+        tracing::warn!("disk almost full");
+        assert!(init("test-app".to_string(), EventFilters::default(), config()).await.is_err());
+        guard.shutdown().await;
+        let requests = webhook.requests();
+        assert!(requests.iter().any(|request| request.body.contains("disk almost full")));
+    }
+}
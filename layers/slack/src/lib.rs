@@ -1,11 +1,42 @@
 #![doc = include_str!("../README.md")]
 
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
 pub use tracing_layer_core::BackgroundWorker;
+pub use tracing_layer_core::WorkerMessage;
 pub use tracing_layer_core::layer::WebhookLayer;
 pub use tracing_layer_core::filters::EventFilters;
+use regex::Regex;
 use serde::Serialize;
+use serde_json::Value;
 use tracing_layer_core::layer::WebhookLayerBuilder;
-use tracing_layer_core::{Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+use tracing::Level;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use tracing_layer_core::Identity;
+pub use tracing_layer_core::clock::{Clock, MockClock, SystemClock};
+pub use tracing_layer_core::BackoffJitter;
+pub use tracing_layer_core::BuildError;
+pub use tracing_layer_core::{MemoryStateStore, StateStore};
+pub use tracing_layer_core::{HeaderMap, HeaderName, HeaderValue};
+pub use tracing_layer_core::timestamp::{TimestampFormat, UtcOffset};
+use tracing_layer_core::{ChannelSender, Config, Presentation, RequestBuilder, ResponseError, StatusCode, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs, DEFAULT_MAX_RETRIES, DEFAULT_REQUEST_TIMEOUT};
+#[cfg(feature = "syslog")]
+pub use tracing_layer_core::syslog::{Facility, SyslogConfig, SyslogTransport};
+#[cfg(feature = "testing")]
+pub use tracing_layer_core::testing::{MockWebhook, ReceivedRequest};
+
+pub use crate::block_kit::SlackBlockKitFactory;
+#[cfg(feature = "init")]
+pub use crate::init::{init, SlackGuard};
+use crate::thread::SlackThreads;
+
+mod block_kit;
+#[cfg(feature = "init")]
+mod init;
+mod split;
+mod thread;
 
 /// Layer for forwarding tracing events to Slack.
 pub struct SlackLayer;
@@ -16,16 +47,88 @@ impl SlackLayer {
     }
 }
 
-impl WebhookMessageFactory for SlackLayer {
-    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+/// The entry point of the previous API of this crate, kept so existing code keeps compiling.
+///
+/// Migrate by replacing `SlackForwardingLayer::new(app_name, target_filters, ..)` with
+/// `SlackLayer::builder(app_name, target_filters)`, setting the optional filters and the config
+/// with the builder's methods, and calling `build()`. The worker's `shutdown` replaces sending
+/// `WorkerMessage::Shutdown` on the channel sender.
+#[deprecated(note = "use `SlackLayer::builder` instead")]
+pub struct SlackForwardingLayer;
+
+// `new` returns the tuple of the previous API rather than `Self`.
+#[allow(deprecated, clippy::new_ret_no_self)]
+impl SlackForwardingLayer {
+    /// Create a layer forwarding events to Slack, along with a sender for the worker's channel and
+    /// the background worker, which must be started for messages to be sent.
+    ///
+    /// Sending `WorkerMessage::Shutdown` on the channel sender stops the worker, as does calling
+    /// `shutdown` on the worker.
+    #[deprecated(note = "use `SlackLayer::builder` instead")]
+    pub fn new(
+        app_name: String,
+        target_filters: EventFilters,
+        message_filters: Option<EventFilters>,
+        event_by_field_filters: Option<EventFilters>,
+        field_exclusion_filters: Option<Vec<Regex>>,
+        config: SlackConfig,
+    ) -> (WebhookLayer<SlackConfig, SlackLayer>, ChannelSender, BackgroundWorker) {
+        let mut builder = SlackLayer::builder(app_name, target_filters).config(config);
+        if let Some(message_filters) = message_filters {
+            builder = builder.message_filters(message_filters);
+        }
+        if let Some(event_by_field_filters) = event_by_field_filters {
+            builder = builder.event_by_field_filters(event_by_field_filters);
+        }
+        if let Some(field_exclusion_filters) = field_exclusion_filters {
+            builder = builder.field_exclusion_filters(field_exclusion_filters);
+        }
+        // The layer has a config and no level filters, so it always builds.
+        let (layer, background_worker) = builder.build().expect("layer with a config");
+        let sender = background_worker.sender();
+        (layer, sender, background_worker)
+    }
+
+    /// Create a layer forwarding events to Slack, using the Slack configuration in the environment.
+    #[deprecated(note = "use `SlackLayer::builder` instead")]
+    pub fn new_from_env(
+        app_name: String,
+        target_filters: EventFilters,
+    ) -> (WebhookLayer<SlackConfig, SlackLayer>, ChannelSender, BackgroundWorker) {
+        Self::new(app_name, target_filters, None, None, None, SlackConfig::new_from_env())
+    }
+}
+
+impl SlackLayer {
+    /// The payload of the message for a single event.
+    fn payload(inputs: WebhookMessageInputs) -> SlackMessagePayload {
         let target = inputs.target;
         let span = inputs.span;
         let metadata = inputs.metadata;
-        let message = inputs.message;
+        let presentation = inputs.presentation;
+        let message = match presentation.emoji {
+            Some(emoji) => format!("{} {}", emoji, inputs.message),
+            None => inputs.message,
+        };
         let app_name = inputs.app_name;
         let source_file = inputs.source_file;
         let source_line = inputs.source_line;
         let event_level = inputs.event_level;
+        let error_sources = inputs.error_sources;
+        let sequence = inputs.sequence;
+        let timestamp = inputs.timestamp;
+        let color = presentation.color.unwrap_or_else(|| default_level_color(event_level).to_string());
+        // Mentions only notify from the text of the message itself, not from its attachments.
+        let mention = presentation.mention;
+        let span_key = inputs.span_key;
+        let raw_attachment = presentation.raw_json.map(|raw_json| {
+            serde_json::json!({
+                "color": color,
+                "title": "Raw event",
+                "text": format!("```{}```", raw_json),
+                "mrkdwn_in": ["text"],
+            })
+        });
 
         #[cfg(feature = "blocks")]
         {
@@ -36,7 +139,7 @@ impl WebhookMessageFactory for SlackLayer {
                 tracing::Level::WARN => ":warning:",
                 tracing::Level::ERROR => ":x:",
             };
-            let blocks = serde_json::json!([
+            let mut blocks = serde_json::json!([
                 {
                     "type": "context",
                     "elements": [
@@ -81,22 +184,60 @@ impl WebhookMessageFactory for SlackLayer {
                     }
                 }
             ]);
-            let blocks_json = blocks.to_string();
+            if let Some(sequence) = sequence {
+                blocks[0]["elements"].as_array_mut().unwrap().push(serde_json::json!({
+                    "type": "mrkdwn",
+                    "text": format!("#{}", sequence),
+                }));
+            }
+            if let Some(timestamp) = timestamp {
+                blocks[0]["elements"].as_array_mut().unwrap().push(serde_json::json!({
+                    "type": "mrkdwn",
+                    "text": timestamp,
+                }));
+            }
+            if !error_sources.is_empty() {
+                let caused_by = error_sources
+                    .iter()
+                    .enumerate()
+                    .map(|(i, source)| format!("{}: {}", i, source))
+                    .collect::<Vec<_>>()
+                    .join("\n");
+                blocks.as_array_mut().unwrap().insert(
+                    2,
+                    serde_json::json!({
+                        "type": "section",
+                        "text": {
+                            "type": "mrkdwn",
+                            "text": format!("*Caused by:*\n```\n{}\n```", caused_by)
+                        }
+                    }),
+                );
+            }
             SlackMessagePayload {
-                text: None,
-                blocks: Some(blocks_json),
+                text: mention,
+                blocks: None,
+                attachments: Some(
+                    std::iter::once(serde_json::json!({ "color": color, "blocks": blocks }))
+                        .chain(raw_attachment)
+                        .collect(),
+                ),
                 webhook_url: inputs.webhook_url.to_string(),
+                level: event_level,
+                span_key,
             }
         }
         #[cfg(not(feature = "blocks"))]
         {
-            let event_level = event.metadata().level().as_str();
-            let source_file = event.metadata().file().unwrap_or("Unknown");
-            let source_line = event.metadata().line().unwrap_or(0);
+            let caused_by: String = error_sources
+                .iter()
+                .enumerate()
+                .map(|(i, source)| format!("\n    {}: {}", i, source))
+                .collect();
             let payload = format!(
                 concat!(
                     "*Trace from {}*\n",
-                    "*Event [{}]*: \"{}\"\n",
+                    "*Event [{}]*: \"{}\"{}\n",
                     "*Target*: _{}_\n",
                     "*Span*: _{}_\n",
                     "*Metadata*:\n",
@@ -105,27 +246,106 @@ impl WebhookMessageFactory for SlackLayer {
                     "```\n",
                     "*Source*: _{}#L{}_",
                 ),
-                app_name, event_level, message, span, target, metadata, source_file, source_line,
+                app_name, event_level.as_str(), message, caused_by, span, target, metadata, source_file, source_line,
             );
+            let payload = match sequence {
+                Some(sequence) => format!("{}\n*Sequence*: _#{}_", payload, sequence),
+                None => payload,
+            };
+            let payload = match timestamp {
+                Some(timestamp) => format!("{}\n*Time*: _{}_", payload, timestamp),
+                None => payload,
+            };
             SlackMessagePayload {
-                text: Some(payload),
+                text: mention,
                 blocks: None,
-                webhook_url: webhook_url.to_string(),
+                attachments: Some(
+                    std::iter::once(serde_json::json!({
+                        "color": color,
+                        "text": payload,
+                        "mrkdwn_in": ["text"],
+                    }))
+                    .chain(raw_attachment)
+                    .collect(),
+                ),
+                webhook_url: inputs.webhook_url,
+                level: event_level,
+                span_key,
             }
         }
     }
 }
 
+impl WebhookMessageFactory for SlackLayer {
+    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+        Self::payload(inputs)
+    }
+
+    /// Combine the events into a single message, with an attachment per event, colored by its level.
+    ///
+    /// Slack rejects messages with more than 50 blocks, and each event takes 5 to 6 of them with the
+    /// `blocks` feature, so larger batches are sent as several messages.
+    fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage {
+        let payloads: Vec<_> = inputs.into_iter().map(Self::payload).collect();
+        let attachments = payloads
+            .iter()
+            .flat_map(|payload| payload.attachments.clone().unwrap_or_default())
+            .collect();
+        SlackMessagePayload {
+            text: mentions(payloads.iter().filter_map(|payload| payload.text.as_deref())),
+            blocks: None,
+            attachments: Some(attachments),
+            webhook_url: payloads[0].webhook_url.clone(),
+            // Levels compare as more verbose being greater, so ERROR is the lowest.
+            level: payloads.iter().map(|payload| payload.level).min().unwrap_or(Level::ERROR),
+            span_key: common_span_key(payloads.iter().map(|payload| payload.span_key)),
+        }
+    }
+}
+
+/// The color of the attachment of an event of the given level, unless overridden with
+/// `SlackConfig::with_level_colors`.
+fn default_level_color(level: Level) -> &'static str {
+    match level {
+        Level::TRACE | Level::DEBUG | Level::INFO => "good",
+        Level::WARN => "warning",
+        Level::ERROR => "danger",
+    }
+}
+
+/// The distinct mentions of the events of a message, separated by spaces, if any.
+fn mentions<'a>(mentions: impl Iterator<Item = &'a str>) -> Option<String> {
+    let mut distinct: Vec<&str> = Vec::new();
+    for mention in mentions {
+        if !distinct.contains(&mention) {
+            distinct.push(mention);
+        }
+    }
+    (!distinct.is_empty()).then(|| distinct.join(" "))
+}
+
+/// The key of the span of every event of a message, if they all belong to the same span.
+fn common_span_key(mut span_keys: impl Iterator<Item = Option<u64>>) -> Option<u64> {
+    let first = span_keys.next()??;
+    span_keys.all(|span_key| span_key == Some(first)).then_some(first)
+}
+
 /// The message sent to Slack. The logged record being "drained" will be
 /// converted into this format.
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub(crate) struct SlackMessagePayload {
     #[serde(skip_serializing_if = "Option::is_none")]
     text: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     blocks: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    attachments: Option<Vec<serde_json::Value>>,
     #[serde(skip_serializing)]
     webhook_url: String,
+    #[serde(skip_serializing)]
+    level: Level,
+    #[serde(skip_serializing)]
+    span_key: Option<u64>,
 }
 
 impl WebhookMessage for SlackMessagePayload {
@@ -136,25 +356,366 @@ impl WebhookMessage for SlackMessagePayload {
     fn serialize(&self) -> String {
         serde_json::to_string(self).expect("failed to serialize slack message")
     }
+
+    fn serialize_parts(&self) -> Vec<String> {
+        split::parts(self).iter().map(WebhookMessage::serialize).collect()
+    }
+
+    fn level(&self) -> Option<Level> {
+        Some(self.level)
+    }
+
+    fn span_key(&self) -> Option<u64> {
+        self.span_key
+    }
+
+    /// Appended as a section to the blocks of the message, if it has blocks, and to its text
+    /// otherwise.
+    fn append_text(&mut self, text: &str) {
+        if let Some(blocks) = &self.blocks {
+            if let Ok(Value::Array(mut blocks)) = serde_json::from_str(blocks) {
+                blocks.push(serde_json::json!({ "type": "section", "text": { "type": "mrkdwn", "text": text } }));
+                self.blocks = Some(Value::from(blocks).to_string());
+                return;
+            }
+        }
+        self.text = Some(match self.text.take() {
+            Some(existing) => format!("{}\n{}", existing, text),
+            None => text.to_string(),
+        });
+    }
+}
+
+/// The endpoint of Slack's Web API posting a message to a channel.
+const WEB_API_URL: &str = "https://slack.com/api/chat.postMessage";
+/// The errors incoming webhooks may respond with as plain text, even with a `200 OK`.
+const SLACK_WEBHOOK_ERRORS: &[&str] = &[
+    "invalid_payload",
+    "invalid_token",
+    "no_text",
+    "no_service",
+    "no_team",
+    "team_disabled",
+    "user_not_found",
+    "channel_not_found",
+    "channel_is_archived",
+    "action_prohibited",
+    "posting_to_general_channel_denied",
+    "too_many_attachments",
+    "rollup_error",
+    "internal_error",
+];
+/// The errors of Slack worth retrying a message for, the others being caused by the message itself or
+/// the configuration.
+const RETRYABLE_SLACK_ERRORS: &[&str] =
+    &["internal_error", "fatal_error", "service_unavailable", "request_timeout", "ratelimited", "rollup_error"];
+
+/// The icon messages are posted with, instead of the one of the app or webhook.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Icon {
+    /// An emoji shortcode, e.g. `:rotating_light:`.
+    Emoji(String),
+    /// The URL of an image.
+    Url(String),
 }
 
 /// Configuration describing how to forward tracing events to Slack.
 pub struct SlackConfig {
     pub(crate) webhook_url: String,
+    /// The bot token and channel messages are posted with through the Web API, rather than with an
+    /// incoming webhook.
+    pub(crate) web_api: Option<(String, String)>,
+    pub(crate) username: Option<String>,
+    pub(crate) icon: Option<Icon>,
+    pub(crate) webhook_urls: Vec<(String, u32)>,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub(crate) tls_identity: Option<Identity>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) routing_key: Option<String>,
+    pub(crate) routing_key_header: String,
+    pub(crate) max_retries: usize,
+    pub(crate) request_timeout: Duration,
+    pub(crate) retry_deadline: Option<Duration>,
+    pub(crate) backoff_jitter: BackoffJitter,
+    pub(crate) headers: HeaderMap,
+    pub(crate) level_colors: HashMap<Level, String>,
+    pub(crate) mention_on_level: Option<(Level, String)>,
+    pub(crate) level_emoji: HashMap<Level, String>,
+    pub(crate) threads: Option<SlackThreads>,
+    pub(crate) pretty_metadata: bool,
+    pub(crate) timestamp_format: Option<TimestampFormat>,
+    pub(crate) dry_run: bool,
+    pub(crate) raw_json: bool,
 }
 
 impl SlackConfig {
     pub fn new(webhook_url: String) -> Self {
-        Self { webhook_url }
+        Self {
+            webhook_url,
+            web_api: None,
+            username: None,
+            icon: None,
+            webhook_urls: Vec::new(),
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            tls_identity: None,
+            clock: Arc::new(SystemClock),
+            routing_key: None,
+            routing_key_header: "X-Routing-Key".to_string(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            retry_deadline: None,
+            backoff_jitter: BackoffJitter::default(),
+            headers: HeaderMap::new(),
+            pretty_metadata: true,
+            timestamp_format: None,
+            dry_run: false,
+            raw_json: false,
+            level_colors: HashMap::new(),
+            mention_on_level: None,
+            level_emoji: HashMap::new(),
+            threads: None,
+        }
+    }
+
+    /// Post messages with Slack's Web API, to `chat.postMessage`, authenticated with the token of a
+    /// bot, e.g. `xoxb-...`, rather than with an incoming webhook. Messages can then be posted to any
+    /// channel the bot is a member of, given by its ID or name, and threaded with `with_threads`, as
+    /// the API responds with the `ts` of the messages posted.
+    ///
+    /// The API responds with `200 OK` even when it fails, e.g. for a channel it cannot find, in which
+    /// case the error in the body of the response is logged and the message is not retried.
+    pub fn web_api(bot_token: String, channel: String) -> Self {
+        Self {
+            web_api: Some((bot_token, channel)),
+            ..Self::new(WEB_API_URL.to_string())
+        }
+    }
+
+    /// Spread messages across several webhook URLs for the same channel, with their relative
+    /// weights, to raise the effective rate limit. A URL that is rate limited is skipped for a
+    /// few seconds.
+    pub fn with_webhook_urls(mut self, webhook_urls: Vec<(String, u32)>) -> Self {
+        self.webhook_urls = webhook_urls;
+        self
+    }
+
+    /// Present a client certificate to the webhook endpoint, for endpoints behind a gateway that
+    /// requires mutual TLS.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn with_tls_identity(mut self, identity: Identity) -> Self {
+        self.tls_identity = Some(identity);
+        self
+    }
+
+    /// The number of attempts at delivering each message before giving up on it, e.g. fewer for a
+    /// rate-limited channel or more for a flaky self-hosted endpoint. Defaults to 10.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// How long to wait for the webhook to respond to a request before retrying it. Defaults to 30
+    /// seconds.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// How long to keep attempting to deliver each message before giving up on it, even if it was
+    /// attempted fewer than `with_max_retries` times. Defaults to no limit.
+    pub fn with_retry_deadline(mut self, retry_deadline: Duration) -> Self {
+        self.retry_deadline = Some(retry_deadline);
+        self
+    }
+
+    /// How the delay between attempts at delivering a message is randomized. Defaults to
+    /// `BackoffJitter::None`.
+    pub fn with_backoff_jitter(mut self, backoff_jitter: BackoffJitter) -> Self {
+        self.backoff_jitter = backoff_jitter;
+        self
+    }
+
+    /// Send a key identifying this service or stream with every message, in the `X-Routing-Key`
+    /// header unless configured otherwise, for webhook receivers aggregating several sources.
+    pub fn with_routing_key(mut self, routing_key: String) -> Self {
+        self.routing_key = Some(routing_key);
+        self
+    }
+
+    /// The name of the header carrying the routing key.
+    pub fn with_routing_key_header(mut self, routing_key_header: String) -> Self {
+        self.routing_key_header = routing_key_header;
+        self
+    }
+
+    /// Add headers to every request, e.g. an `Authorization` header for an endpoint behind an
+    /// authenticating proxy.
+    pub fn with_headers(mut self, headers: HeaderMap) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Override the color of the sidebar of messages for some levels, as `good`, `warning`, `danger`,
+    /// or a hex code such as `#439FE0`. By default, INFO and more verbose levels are green, WARN is
+    /// yellow, and ERROR is red.
+    pub fn with_level_colors(mut self, level_colors: HashMap<Level, String>) -> Self {
+        self.level_colors = level_colors;
+        self
+    }
+
+    /// Mention someone in messages for events at or above the given level, e.g.
+    /// `(Level::ERROR, "<!here>")` to page the channel on errors, or `<@U12345>` for a user, while
+    /// less severe events are sent quietly. The mention is written as is, in Slack's syntax.
+    pub fn with_mention_on_level(mut self, level: Level, mention: String) -> Self {
+        self.mention_on_level = Some((level, mention));
+        self
+    }
+
+    /// Prepend an emoji to the messages of events of some levels, as a visual indicator of their
+    /// level, e.g. `:red_circle:` for ERROR and `:large_yellow_circle:` for WARN. Emoji are written as
+    /// is, either as shortcodes or as Unicode. Events of the other levels have no prefix.
+    pub fn with_level_emoji(mut self, level_emoji: HashMap<Level, String>) -> Self {
+        self.level_emoji = level_emoji;
+        self
+    }
+
+    /// Post the messages of a span in the thread of its first message, rather than each in the
+    /// channel, so operations logging many events do not flood it.
+    ///
+    /// Threads are started from the `ts` of the posted message, which only endpoints responding with
+    /// the message do, such as the Web API of `SlackConfig::web_api` or a relay in front of it.
+    /// Incoming webhooks only respond with `ok`, so their messages are not threaded. Messages sent
+    /// concurrently with the first one of their span, e.g. with `BackgroundWorker::concurrency`, may
+    /// not be threaded either.
+    pub fn with_threads(mut self) -> Self {
+        self.threads = Some(SlackThreads::default());
+        self
+    }
+
+    /// Like `with_threads`, keeping the `ts` of the threads in the given state store rather than in
+    /// memory, where only the latest 1000 are remembered, e.g. an external store also used by the
+    /// `DedupFilter` and `RateLimitFilter`. Threads are never shared with other processes, whose
+    /// spans are their own.
+    pub fn with_thread_store(mut self, store: Arc<dyn StateStore>) -> Self {
+        self.threads = Some(SlackThreads::new(store));
+        self
+    }
+
+    /// Whether to send the fields of events as pretty-printed JSON, or as compact JSON leaving more
+    /// room for them under the length limits of messages. Defaults to `true`.
+    pub fn with_pretty_metadata(mut self, pretty_metadata: bool) -> Self {
+        self.pretty_metadata = pretty_metadata;
+        self
+    }
+
+    /// Show when each event was recorded in its message, rendered in the given format and time zone,
+    /// e.g. `TimestampFormat::rfc3339()`, rather than only when the destination received it, which
+    /// may be later when messages are queued or retried.
+    pub fn with_timestamp_format(mut self, timestamp_format: TimestampFormat) -> Self {
+        self.timestamp_format = Some(timestamp_format);
+        self
+    }
+
+    /// Attach the fields of each event as compact JSON to its message, below their rendering, for
+    /// tooling consuming the messages. Unlike the rendered fields, they are neither truncated nor
+    /// humanized, but fields matching the field exclusion filters are still left out. Defaults to
+    /// `false`.
+    pub fn with_raw_json(mut self, raw_json: bool) -> Self {
+        self.raw_json = raw_json;
+        self
+    }
+
+    /// Post messages under the given name, e.g. to tell apart the layers of several services posting
+    /// to the same channel, instead of the name of the app or webhook.
+    ///
+    /// Slack only lets apps created before 2019 override it with an incoming webhook, and the Web API
+    /// requires the `chat:write.customize` scope.
+    pub fn with_username(mut self, username: String) -> Self {
+        self.username = Some(username);
+        self
+    }
+
+    /// Post messages with the given icon, as an emoji or the URL of an image, instead of the icon of
+    /// the app or webhook. It can be overridden like the username, see `with_username`.
+    pub fn with_icon(mut self, icon: Icon) -> Self {
+        self.icon = Some(icon);
+        self
+    }
+
+    /// Print the body of each message to stderr instead of sending it, e.g. to tune filters in
+    /// staging without posting to the channel. Defaults to `false`.
+    pub fn with_dry_run(mut self, dry_run: bool) -> Self {
+        self.dry_run = dry_run;
+        self
+    }
+
+    /// Use another source of the current time, e.g. a `MockClock` to test time-based features.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
     }
 
     /// Create a new config for forwarding messages to Slack using configuration
     /// available in the environment.
     ///
     /// Required env vars:
-    ///   * SLACK_WEBHOOK_URL
+    ///   * SLACK_WEBHOOK_URL, or SLACK_BOT_TOKEN and SLACK_CHANNEL to post with the Web API
+    ///
+    /// Optional env vars:
+    ///   * WEBHOOK_ROUTING_KEY
+    ///   * WEBHOOK_ROUTING_KEY_HEADER (default: `X-Routing-Key`)
+    ///   * SLACK_USERNAME
+    ///   * SLACK_ICON_EMOJI, or SLACK_ICON_URL
+    ///   * SLACK_DRY_RUN (`1` or `true` to print messages instead of sending them)
     pub fn new_from_env() -> Self {
-        Self::new(std::env::var("SLACK_WEBHOOK_URL").expect("slack webhook url in env"))
+        Self::try_new_from_env().expect("slack webhook url in env")
+    }
+
+    /// Like `new_from_env`, but returns an error naming the missing variable rather than panicking.
+    pub fn try_new_from_env() -> Result<Self, BuildError> {
+        let mut config = match (std::env::var("SLACK_WEBHOOK_URL"), std::env::var("SLACK_BOT_TOKEN")) {
+            (Ok(webhook_url), _) => Self::new(webhook_url),
+            (Err(_), Ok(bot_token)) => {
+                let channel =
+                    std::env::var("SLACK_CHANNEL").map_err(|_| BuildError::MissingEnvVar { var: "SLACK_CHANNEL" })?;
+                Self::web_api(bot_token, channel)
+            }
+            (Err(_), Err(_)) => return Err(BuildError::MissingEnvVar { var: "SLACK_WEBHOOK_URL" }),
+        };
+        config.routing_key = std::env::var("WEBHOOK_ROUTING_KEY").ok();
+        if let Ok(routing_key_header) = std::env::var("WEBHOOK_ROUTING_KEY_HEADER") {
+            config.routing_key_header = routing_key_header;
+        }
+        config.username = std::env::var("SLACK_USERNAME").ok();
+        config.icon = std::env::var("SLACK_ICON_EMOJI")
+            .map(Icon::Emoji)
+            .or_else(|_| std::env::var("SLACK_ICON_URL").map(Icon::Url))
+            .ok();
+        config.dry_run = matches!(std::env::var("SLACK_DRY_RUN").as_deref(), Ok("1") | Ok("true"));
+        Ok(config)
+    }
+
+    /// The body of a message, with the channel, username, and icon to post it with, if any.
+    fn with_sender(&self, body: String) -> String {
+        if self.web_api.is_none() && self.username.is_none() && self.icon.is_none() {
+            return body;
+        }
+        let mut message = match serde_json::from_str::<Value>(&body) {
+            Ok(Value::Object(message)) => message,
+            _ => return body,
+        };
+        if let Some((_, channel)) = &self.web_api {
+            message.insert("channel".to_string(), Value::String(channel.clone()));
+        }
+        if let Some(username) = &self.username {
+            message.insert("username".to_string(), Value::String(username.clone()));
+        }
+        match &self.icon {
+            Some(Icon::Emoji(emoji)) => message.insert("icon_emoji".to_string(), Value::String(emoji.clone())),
+            Some(Icon::Url(url)) => message.insert("icon_url".to_string(), Value::String(url.clone())),
+            None => None,
+        };
+        Value::Object(message).to_string()
     }
 }
 
@@ -169,12 +730,471 @@ impl Config for SlackConfig {
         &self.webhook_url
     }
 
+    fn webhook_urls(&self) -> &[(String, u32)] {
+        &self.webhook_urls
+    }
+
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    fn tls_identity(&self) -> Option<Identity> {
+        self.tls_identity.clone()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    fn retry_deadline(&self) -> Option<Duration> {
+        self.retry_deadline
+    }
+
+    fn backoff_jitter(&self) -> BackoffJitter {
+        self.backoff_jitter
+    }
+
+    fn presentation(&self, level: Level) -> Presentation {
+        // Levels compare as more verbose being greater, so more severe levels are lower.
+        let mention = match &self.mention_on_level {
+            Some((min_level, mention)) if level <= *min_level => Some(mention.clone()),
+            _ => None,
+        };
+        Presentation {
+            color: self.level_colors.get(&level).cloned(),
+            mention,
+            emoji: self.level_emoji.get(&level).cloned(),
+            include_raw_json: self.raw_json,
+            raw_json: None,
+            template: None,
+        }
+    }
+
+    fn routing_key(&self) -> Option<&str> {
+        self.routing_key.as_deref()
+    }
+
+    fn handle_response(&self, message: &dyn WebhookMessage, status: StatusCode, body: &str) {
+        if let (Some(threads), Some(span_key)) = (&self.threads, message.span_key()) {
+            if status.is_success() {
+                threads.record(span_key, body);
+            }
+        }
+    }
+
+    fn response_error(&self, _status: StatusCode, body: &str) -> Option<ResponseError> {
+        if self.web_api.is_some() {
+            return match serde_json::from_str::<Value>(body) {
+                Ok(response) if response["ok"] == false => {
+                    Some(slack_error(response["error"].as_str().unwrap_or("unknown_error")))
+                }
+                _ => None,
+            };
+        }
+        // Incoming webhooks respond with `ok`, or with their error as plain text.
+        let error = body.trim();
+        SLACK_WEBHOOK_ERRORS.contains(&error).then(|| slack_error(error))
+    }
+
+    fn sign_request(&self, request: RequestBuilder, _body: &[u8]) -> RequestBuilder {
+        match &self.web_api {
+            Some((bot_token, _)) => request.bearer_auth(bot_token),
+            None => request,
+        }
+    }
+
+    fn prepare_body(&self, message: &dyn WebhookMessage, body: String) -> String {
+        let body = self.with_sender(body);
+        match (&self.threads, message.span_key()) {
+            (Some(threads), Some(span_key)) => threads.in_thread(span_key, body),
+            _ => body,
+        }
+    }
+
+    fn routing_key_header(&self) -> &str {
+        &self.routing_key_header
+    }
+
+    fn headers(&self) -> &HeaderMap {
+        &self.headers
+    }
+
+    fn pretty_metadata(&self) -> bool {
+        self.pretty_metadata
+    }
+
+    fn timestamp_format(&self) -> Option<&TimestampFormat> {
+        self.timestamp_format.as_ref()
+    }
+
+    fn dry_run(&self) -> bool {
+        self.dry_run
+    }
+
     fn new_from_env() -> Self where Self: Sized {
         Self::new_from_env()
     }
+
+    fn try_new_from_env() -> Result<Self, BuildError> where Self: Sized {
+        Self::try_new_from_env()
+    }
+}
+
+/// The kind of failure an error of Slack is.
+fn slack_error(error: &str) -> ResponseError {
+    if RETRYABLE_SLACK_ERRORS.contains(&error) {
+        ResponseError::Retryable(error.to_string())
+    } else {
+        ResponseError::Permanent(error.to_string())
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    use regex::Regex;
+    use serde_json::{json, Value};
+    use tracing::Level;
+    use tracing_layer_core::testing::MockWebhook;
+    use tracing_layer_core::{
+        testing, Client, Config, ResponseError, StatusCode, WebhookMessage, WebhookMessageFactory,
+        WebhookMessageInputs,
+    };
+    use tracing_subscriber::layer::SubscriberExt;
+    use tracing_subscriber::Registry;
+
+    #[allow(deprecated)]
+    use crate::SlackForwardingLayer;
+    use crate::{
+        BuildError, EventFilters, HeaderMap, HeaderValue, Icon, MemoryStateStore, SlackBlockKitFactory, SlackConfig,
+        SlackLayer, WorkerMessage,
+    };
+
+    fn inputs() -> WebhookMessageInputs {
+        WebhookMessageInputs {
+            webhook_url: "https://hooks.slack.com/services/test".to_string(),
+            span: "create_user".to_string(),
+            ..testing::inputs()
+        }
+    }
+
+    #[allow(deprecated)]
+    #[tokio::test]
+    async fn the_forwarding_layer_builds_a_layer_with_the_given_filters() {
+        let webhook = MockWebhook::start().await;
+        let message_filters = EventFilters::new(Some(vec![Regex::new("^disk").unwrap()]), None);
+        let (layer, sender, background_worker) = SlackForwardingLayer::new(
+            "test-app".to_string(),
+            EventFilters::default(),
+            Some(message_filters),
+            None,
+            None,
+            SlackConfig::new(webhook.url()),
+        );
+        background_worker.start().await;
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            tracing::warn!("cpu almost idle");
+            tracing::warn!("disk almost full");
+        });
+        // The worker is stopped with the returned sender, as with the previous API.
+        sender.send(WorkerMessage::Shutdown).unwrap();
+        background_worker.shutdown().await;
+        let requests = webhook.requests();
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].body.contains("disk almost full"));
+    }
 
+    #[tokio::test]
+    async fn messages_are_sent_with_the_headers_of_the_config() {
+        let webhook = MockWebhook::start().await;
+        let mut headers = HeaderMap::new();
+        headers.insert("authorization", HeaderValue::from_static("Bearer secret"));
+        let (layer, background_worker) = SlackLayer::builder("test-app".to_string(), EventFilters::default())
+            .config(SlackConfig::new(webhook.url()).with_headers(headers))
+            .build()
+            .unwrap();
+        background_worker.start().await;
+        tracing::subscriber::with_default(Registry::default().with(layer), || {
+            tracing::warn!("disk almost full");
+        });
+        background_worker.shutdown().await;
+        let requests = webhook.requests();
+        assert_eq!(requests[0].headers["authorization"], "Bearer secret");
+        assert_eq!(requests[0].headers["content-type"], "application/json");
+    }
+
+    #[test]
+    fn message_is_sent_to_the_configured_webhook() {
+        let message = SlackLayer::create(inputs());
+        assert_eq!(message.webhook_url(), "https://hooks.slack.com/services/test");
+        assert_eq!(message.level(), Some(Level::WARN));
+    }
+
+    #[cfg(feature = "blocks")]
+    #[test]
+    fn message_serializes_to_blocks() {
+        let payload: Value = serde_json::from_str(&SlackLayer::create(inputs()).serialize()).unwrap();
+        assert_eq!(payload.get("text"), None);
+        assert_eq!(payload["attachments"][0]["color"], "warning");
+        assert_eq!(
+            payload["attachments"][0]["blocks"],
+            json!([
+                {
+                    "type": "context",
+                    "elements": [{ "type": "mrkdwn", "text": "test-app - :warning: *WARN*" }]
+                },
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": "\"_user created_\"" }
+                },
+                {
+                    "type": "section",
+                    "fields": [
+                        { "type": "mrkdwn", "text": "*Target Span*\napp::users::create_user" },
+                        { "type": "mrkdwn", "text": "*Source*\nsrc/users.rs#L42" }
+                    ]
+                },
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": "*Metadata:*" }
+                },
+                {
+                    "type": "section",
+                    "text": { "type": "mrkdwn", "text": "```\n{}\n```" }
+                }
+            ])
+        );
+    }
+
+    #[cfg(feature = "blocks")]
+    #[test]
+    fn sequence_number_is_rendered_in_the_context() {
+        let mut inputs = inputs();
+        inputs.sequence = Some(7);
+        let payload: Value = serde_json::from_str(&SlackLayer::create(inputs).serialize()).unwrap();
+        let blocks = &payload["attachments"][0]["blocks"];
+        assert_eq!(blocks[0]["elements"][1], json!({ "type": "mrkdwn", "text": "#7" }));
+    }
+
+    #[test]
+    fn configured_level_color_overrides_the_default() {
+        let mut inputs = inputs();
+        inputs.presentation.color = Some("#439FE0".to_string());
+        let payload: Value = serde_json::from_str(&SlackLayer::create(inputs).serialize()).unwrap();
+        assert_eq!(payload["attachments"][0]["color"], "#439FE0");
+    }
+
+    #[test]
+    fn level_emoji_prefixes_the_message() {
+        let config = SlackConfig::new("https://hooks.slack.com/services/test".to_string())
+            .with_level_emoji(HashMap::from([(Level::WARN, ":large_yellow_circle:".to_string())]));
+        assert_eq!(config.presentation(Level::ERROR).emoji, None);
+
+        let mut inputs = inputs();
+        inputs.presentation = config.presentation(inputs.event_level);
+        let body = SlackLayer::create(inputs).serialize();
+        assert!(body.contains(":large_yellow_circle: user created"), "{}", body);
+    }
+
+    #[test]
+    fn raw_json_is_attached_below_the_event() {
+        let mut inputs = inputs();
+        inputs.presentation.raw_json = Some(json!({ "message": "user created", "user_id": 7 }).to_string());
+        let body: Value = serde_json::from_str(&SlackLayer::create(inputs).serialize()).unwrap();
+        let attachments = body["attachments"].as_array().unwrap();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(attachments[1]["title"], "Raw event");
+        assert_eq!(attachments[1]["text"], r#"```{"message":"user created","user_id":7}```"#);
+    }
+
+    #[test]
+    fn mentions_are_sent_in_the_text_for_severe_events() {
+        let config = SlackConfig::new("https://hooks.slack.com/services/test".to_string())
+            .with_mention_on_level(Level::WARN, "<!here>".to_string());
+        assert_eq!(config.presentation(Level::ERROR).mention.as_deref(), Some("<!here>"));
+        assert_eq!(config.presentation(Level::INFO).mention, None);
+
+        let mut severe = inputs();
+        severe.presentation = config.presentation(severe.event_level);
+        let body = SlackLayer::combine(vec![severe.clone(), severe, inputs()]).serialize();
+        // The mention is written as is rather than escaped, and only once per message.
+        assert!(body.starts_with(r#"{"text":"<!here>","#), "{}", body);
+    }
+
+    #[test]
+    fn messages_of_a_span_are_posted_in_its_thread() {
+        let config = SlackConfig::new("https://slack.com/api/chat.postMessage".to_string()).with_threads();
+        let mut inputs = inputs();
+        inputs.span_key = Some(3);
+        let first = SlackLayer::create(inputs.clone());
+        // Incoming webhooks do not respond with the posted message, so there is no thread to use.
+        config.handle_response(&first, StatusCode::OK, "ok");
+        assert!(!config.prepare_body(&first, first.serialize()).contains("thread_ts"));
+
+        config.handle_response(&first, StatusCode::OK, r#"{"ok":true,"ts":"1503435956.000247"}"#);
+        let next = SlackLayer::create(inputs.clone());
+        let body: Value = serde_json::from_str(&config.prepare_body(&next, next.serialize())).unwrap();
+        assert_eq!(body["thread_ts"], "1503435956.000247");
+
+        inputs.span_key = Some(4);
+        let other = SlackLayer::create(inputs);
+        assert!(!config.prepare_body(&other, other.serialize()).contains("thread_ts"));
+    }
+
+    #[test]
+    fn web_api_messages_are_posted_to_the_channel_with_the_bot_token() {
+        let config = SlackConfig::web_api("xoxb-token".to_string(), "C0123456".to_string());
+        let message = SlackLayer::create(inputs());
+        assert_eq!(config.webhook_url(), "https://slack.com/api/chat.postMessage");
+        let body: Value = serde_json::from_str(&config.prepare_body(&message, message.serialize())).unwrap();
+        assert_eq!(body["channel"], "C0123456");
+
+        let request = config.sign_request(Client::new().post(config.webhook_url()), b"").build().unwrap();
+        assert_eq!(request.headers()["Authorization"], "Bearer xoxb-token");
+
+        // The API reports failures in the body of successful responses.
+        let failure = r#"{"ok":false,"error":"channel_not_found"}"#;
+        let error = Some(ResponseError::Permanent("channel_not_found".to_string()));
+        assert_eq!(config.response_error(StatusCode::OK, failure), error);
+        assert_eq!(config.response_error(StatusCode::OK, r#"{"ok":true,"ts":"1.2"}"#), None);
+    }
+
+    #[test]
+    fn slack_errors_in_successful_responses_are_failures() {
+        let config = SlackConfig::new("https://hooks.slack.com/services/test".to_string());
+        assert_eq!(config.response_error(StatusCode::OK, "ok"), None);
+        let error = Some(ResponseError::Permanent("invalid_payload".to_string()));
+        assert_eq!(config.response_error(StatusCode::OK, "invalid_payload"), error);
+        // Internal errors of Slack are worth retrying.
+        let error = Some(ResponseError::Retryable("internal_error".to_string()));
+        assert_eq!(config.response_error(StatusCode::OK, "internal_error\n"), error);
+
+        let config = SlackConfig::web_api("xoxb-token".to_string(), "C0123456".to_string());
+        let error = Some(ResponseError::Retryable("ratelimited".to_string()));
+        assert_eq!(config.response_error(StatusCode::OK, r#"{"ok":false,"error":"ratelimited"}"#), error);
+    }
+
+    #[test]
+    fn messages_are_posted_with_the_configured_username_and_icon() {
+        let message = SlackLayer::create(inputs());
+        let config = SlackConfig::new("https://hooks.slack.com/services/test".to_string());
+        assert_eq!(config.prepare_body(&message, message.serialize()), message.serialize());
+
+        let config = config.with_username("billing-api".to_string()).with_icon(Icon::Emoji(":money:".to_string()));
+        let body: Value = serde_json::from_str(&config.prepare_body(&message, message.serialize())).unwrap();
+        assert_eq!((&body["username"], &body["icon_emoji"]), (&json!("billing-api"), &json!(":money:")));
+        assert!(body.get("icon_url").is_none());
+
+        let config = config.with_icon(Icon::Url("https://example.com/icon.png".to_string()));
+        let body: Value = serde_json::from_str(&config.prepare_body(&message, message.serialize())).unwrap();
+        assert_eq!(body["icon_url"], "https://example.com/icon.png");
+        assert!(body.get("icon_emoji").is_none());
+    }
+
+    #[test]
+    fn threads_are_kept_in_the_state_store() {
+        let store = Arc::new(MemoryStateStore::default());
+        let url = "https://slack.com/api/chat.postMessage".to_string();
+        let config = SlackConfig::new(url.clone()).with_thread_store(store.clone());
+        let mut inputs = inputs();
+        inputs.span_key = Some(3);
+        let first = SlackLayer::create(inputs.clone());
+        config.handle_response(&first, StatusCode::OK, r#"{"ok":true,"ts":"1503435956.000247"}"#);
+        let next = SlackLayer::create(inputs);
+        assert!(config.prepare_body(&next, next.serialize()).contains("1503435956.000247"));
+
+        // Another process sharing the store does not mistake the spans of this one for its own.
+        let other = SlackConfig::new(url).with_thread_store(store);
+        assert!(!other.prepare_body(&next, next.serialize()).contains("thread_ts"));
+    }
+
+    #[test]
+    fn block_kit_factory_lists_fields_in_sections() {
+        let mut inputs = inputs();
+        inputs.metadata = json!({ "user_id": 7, "plan": "pro" }).to_string();
+        let payload: Value = serde_json::from_str(&SlackBlockKitFactory::create(inputs).serialize()).unwrap();
+        let blocks: Value = serde_json::from_str(payload["blocks"].as_str().unwrap()).unwrap();
+        assert_eq!(blocks[0]["type"], "header");
+        assert_eq!(blocks[0]["text"]["text"], "user created");
+        assert_eq!(
+            blocks[3]["fields"],
+            json!([
+                { "type": "mrkdwn", "text": "*plan*\npro" },
+                { "type": "mrkdwn", "text": "*user_id*\n7" }
+            ])
+        );
+    }
+
+    #[test]
+    fn invalid_level_filters_fail_the_build() {
+        let config = SlackConfig::new("https://hooks.slack.com/services/test".to_string());
+        let built = SlackLayer::builder("test-app".to_string(), EventFilters::default())
+            .config(config)
+            .level_filters("warn,app::db=loud".to_string())
+            .build();
+        assert!(matches!(built, Err(BuildError::InvalidLevelFilters { value }) if value == "warn,app::db=loud"));
+    }
+
+    #[test]
+    fn block_kit_factory_falls_back_to_text_beyond_the_block_limit() {
+        let batch = (0..13).map(|_| inputs()).collect();
+        let payload: Value = serde_json::from_str(&SlackBlockKitFactory::combine(batch).serialize()).unwrap();
+        let blocks: Value = serde_json::from_str(payload["blocks"].as_str().unwrap()).unwrap();
+        // A text block per event, separated by dividers.
+        assert_eq!(blocks.as_array().unwrap().len(), 25);
+        assert_eq!(blocks[0]["type"], "section");
+    }
+
+    #[test]
+    fn block_kit_messages_beyond_the_block_limit_are_split() {
+        let batch = (0..30).map(|_| inputs()).collect();
+        let parts = SlackBlockKitFactory::combine(batch).serialize_parts();
+        assert_eq!(parts.len(), 2);
+        for (i, part) in parts.iter().enumerate() {
+            let payload: Value = serde_json::from_str(part).unwrap();
+            let blocks: Value = serde_json::from_str(payload["blocks"].as_str().unwrap()).unwrap();
+            assert!(blocks.as_array().unwrap().len() <= 50);
+            assert_eq!(blocks[0]["elements"][0]["text"], format!("({}/2)", i + 1));
+            // Parts end between events, rather than on a divider.
+            assert_eq!(blocks[1]["type"], "section");
+        }
+    }
+
+    #[test]
+    fn long_stack_traces_are_split_into_numbered_messages() {
+        let mut inputs = inputs();
+        // Beyond what 50 sections of 3000 characters can hold.
+        let trace: Vec<_> = (0..4000)
+            .map(|i| format!("{:>4}: app::users::create_user at src/users.rs:{}", i, i))
+            .collect();
+        inputs.metadata = trace.join("\n");
+        let parts = SlackLayer::create(inputs).serialize_parts();
+        assert!(parts.len() > 1);
+
+        let mut sent = String::new();
+        for (i, part) in parts.iter().enumerate() {
+            let payload: Value = serde_json::from_str(part).unwrap();
+            assert!(payload["text"].as_str().unwrap().ends_with(&format!("({}/{})", i + 1, parts.len())));
+            for attachment in payload["attachments"].as_array().unwrap() {
+                let texts = match attachment["blocks"].as_array() {
+                    Some(blocks) => blocks.iter().filter_map(|block| block["text"]["text"].as_str()).collect(),
+                    None => vec![attachment["text"].as_str().unwrap()],
+                };
+                for text in texts {
+                    assert!(text.chars().count() <= 4000);
+                    // Code blocks are closed at the end of a part and reopened in the next.
+                    assert_eq!(text.matches("```").count() % 2, 0, "{}", text);
+                    sent.push_str(text);
+                }
+            }
+        }
+        assert!(sent.contains(trace.last().unwrap()));
+    }
 }
@@ -0,0 +1,230 @@
+use serde_json::Value;
+
+use crate::SlackMessagePayload;
+
+/// The maximum number of blocks in a message.
+const MAX_BLOCKS: usize = 50;
+/// The maximum number of characters of the text of a section block.
+const MAX_SECTION_CHARS: usize = 3000;
+/// The maximum number of characters of the texts of the attachments of a message. Slack truncates
+/// longer messages, and recommends keeping them under this length.
+const MAX_TEXT_CHARS: usize = 4000;
+const CODE_FENCE: &str = "```";
+
+/// The messages to send in order for a message exceeding Slack's limits on the number of blocks and
+/// the length of texts, numbered `(1/3)`, `(2/3)`, ... Texts are split on line boundaries where
+/// possible, closing a code block at the end of a part and reopening it at the start of the next,
+/// and sections with too long a text are split into several sections.
+///
+/// A message within the limits is sent as a single message.
+pub(crate) fn parts(payload: &SlackMessagePayload) -> Vec<SlackMessagePayload> {
+    if let Some(blocks) = &payload.blocks {
+        let blocks = match serde_json::from_str::<Value>(blocks) {
+            Ok(Value::Array(blocks)) => blocks,
+            _ => return vec![payload.clone()],
+        };
+        let blocks = split_sections(blocks);
+        if blocks.len() <= MAX_BLOCKS {
+            return vec![SlackMessagePayload {
+                blocks: Some(Value::from(blocks).to_string()),
+                ..payload.clone()
+            }];
+        }
+        // Keep room for the block numbering each part.
+        let chunks = block_chunks(blocks, MAX_BLOCKS - 1);
+        let total = chunks.len();
+        return chunks
+            .into_iter()
+            .enumerate()
+            .map(|(i, mut chunk)| {
+                chunk.insert(0, numbering_block(i + 1, total));
+                SlackMessagePayload {
+                    text: numbered_text(payload.text.as_deref(), i + 1, total),
+                    blocks: Some(Value::from(chunk).to_string()),
+                    ..payload.clone()
+                }
+            })
+            .collect();
+    }
+    let attachments = match &payload.attachments {
+        Some(attachments) => attachments,
+        None => return vec![payload.clone()],
+    };
+    let mut chunks = attachment_chunks(attachments.iter().flat_map(split_attachment));
+    if chunks.len() <= 1 {
+        return vec![SlackMessagePayload {
+            attachments: Some(chunks.pop().unwrap_or_default()),
+            ..payload.clone()
+        }];
+    }
+    let total = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .map(|(i, chunk)| SlackMessagePayload {
+            text: numbered_text(payload.text.as_deref(), i + 1, total),
+            attachments: Some(chunk),
+            ..payload.clone()
+        })
+        .collect()
+}
+
+/// The text of a part of a message: the text of the message, e.g. its mentions, in the first part
+/// only, followed by the position of the part.
+fn numbered_text(text: Option<&str>, part: usize, total: usize) -> Option<String> {
+    Some(match text {
+        Some(text) if part == 1 => format!("{} ({}/{})", text, part, total),
+        _ => format!("({}/{})", part, total),
+    })
+}
+
+fn numbering_block(part: usize, total: usize) -> Value {
+    serde_json::json!({
+        "type": "context",
+        "elements": [{ "type": "mrkdwn", "text": format!("({}/{})", part, total) }],
+    })
+}
+
+/// The attachment, or the pieces of it which each fit in a message.
+fn split_attachment(attachment: &Value) -> Vec<Value> {
+    if let Some(Value::Array(blocks)) = attachment.get("blocks") {
+        return block_chunks(split_sections(blocks.clone()), MAX_BLOCKS)
+            .into_iter()
+            .map(|blocks| {
+                let mut piece = attachment.clone();
+                piece["blocks"] = Value::from(blocks);
+                piece
+            })
+            .collect();
+    }
+    if let Some(Value::String(text)) = attachment.get("text") {
+        return split_text(text, MAX_TEXT_CHARS)
+            .into_iter()
+            .map(|text| {
+                let mut piece = attachment.clone();
+                piece["text"] = Value::from(text);
+                piece
+            })
+            .collect();
+    }
+    vec![attachment.clone()]
+}
+
+/// The attachments grouped into messages, in order, within the limits on blocks and text.
+fn attachment_chunks(attachments: impl Iterator<Item = Value>) -> Vec<Vec<Value>> {
+    let mut chunks: Vec<Vec<Value>> = Vec::new();
+    let mut chunk = Vec::new();
+    let (mut blocks, mut chars) = (0, 0);
+    for attachment in attachments {
+        let attachment_blocks = attachment.get("blocks").and_then(Value::as_array).map_or(0, Vec::len);
+        let attachment_chars = attachment.get("text").and_then(Value::as_str).map_or(0, |t| t.chars().count());
+        let full = blocks + attachment_blocks > MAX_BLOCKS || chars + attachment_chars > MAX_TEXT_CHARS;
+        if !chunk.is_empty() && full {
+            chunks.push(std::mem::take(&mut chunk));
+            blocks = 0;
+            chars = 0;
+        }
+        blocks += attachment_blocks;
+        chars += attachment_chars;
+        chunk.push(attachment);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// The blocks, with the sections whose text is too long split into several sections.
+fn split_sections(blocks: Vec<Value>) -> Vec<Value> {
+    let mut split = Vec::with_capacity(blocks.len());
+    for block in blocks {
+        let text = match block.pointer("/text/text") {
+            Some(Value::String(text)) if block["type"] == "section" && text.chars().count() > MAX_SECTION_CHARS => {
+                text.clone()
+            }
+            _ => {
+                split.push(block);
+                continue;
+            }
+        };
+        for text in split_text(&text, MAX_SECTION_CHARS) {
+            let mut section = block.clone();
+            section["text"]["text"] = Value::from(text);
+            split.push(section);
+        }
+    }
+    split
+}
+
+/// The blocks grouped into chunks of at most `max` blocks. A chunk ends at its last divider, which
+/// is dropped, when it has one, so the blocks of an event stay together.
+fn block_chunks(blocks: Vec<Value>, max: usize) -> Vec<Vec<Value>> {
+    let mut chunks = Vec::new();
+    let mut chunk: Vec<Value> = Vec::new();
+    for block in blocks {
+        if chunk.len() == max {
+            let rest = match chunk.iter().rposition(|block| block["type"] == "divider") {
+                Some(divider) if divider > 0 => {
+                    let rest = chunk.split_off(divider + 1);
+                    chunk.pop();
+                    rest
+                }
+                _ => Vec::new(),
+            };
+            chunks.push(std::mem::replace(&mut chunk, rest));
+        }
+        chunk.push(block);
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// Split a text into chunks of at most `max` characters, on line boundaries where possible. A chunk
+/// ending inside a code block closes it, and the next chunk reopens it.
+fn split_text(text: &str, max: usize) -> Vec<String> {
+    if text.chars().count() <= max {
+        return vec![text.to_string()];
+    }
+    // Keep room for closing and reopening a code block.
+    let max = max - 2 * CODE_FENCE.len();
+    let mut chunks = Vec::new();
+    let mut chunk = String::new();
+    let mut chunk_chars = 0;
+    let mut in_code = false;
+    for line in lines(text, max - CODE_FENCE.len()) {
+        let line_chars = line.chars().count();
+        if chunk_chars + line_chars > max && chunk_chars > 0 {
+            if in_code {
+                chunk.push_str(CODE_FENCE);
+            }
+            chunks.push(std::mem::take(&mut chunk));
+            chunk_chars = 0;
+            if in_code {
+                chunk.push_str(CODE_FENCE);
+                chunk_chars = CODE_FENCE.len();
+            }
+        }
+        chunk.push_str(&line);
+        chunk_chars += line_chars;
+        if line.matches(CODE_FENCE).count() % 2 == 1 {
+            in_code = !in_code;
+        }
+    }
+    if !chunk.is_empty() {
+        chunks.push(chunk);
+    }
+    chunks
+}
+
+/// The lines of a text, with their line break, lines longer than `max` characters being cut into
+/// pieces of at most `max` characters.
+fn lines(text: &str, max: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let chars: Vec<char> = line.chars().collect();
+        lines.extend(chars.chunks(max).map(|piece| piece.iter().collect::<String>()));
+    }
+    lines
+}
@@ -0,0 +1,80 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde_json::Value;
+use tracing_layer_core::{MemoryStateStore, StateStore};
+
+/// The maximum number of spans whose thread is remembered in memory, the oldest being forgotten
+/// first.
+const MAX_THREADS: usize = 1000;
+
+static NEXT_THREADS_ID: AtomicU64 = AtomicU64::new(0);
+
+/// The `ts` of the first message of each span, whose thread the following messages of the span are
+/// posted in, kept in a state store.
+///
+/// Span keys are only unique within a process, so the threads are keyed by the process as well,
+/// keeping those of several instances sharing a store, or of a restarted one, apart, and by config
+/// in case several share the store within a process.
+#[derive(Debug)]
+pub(crate) struct SlackThreads {
+    store: Arc<dyn StateStore>,
+    process: String,
+}
+
+impl Default for SlackThreads {
+    fn default() -> Self {
+        Self::new(Arc::new(MemoryStateStore::new(MAX_THREADS)))
+    }
+}
+
+impl SlackThreads {
+    pub(crate) fn new(store: Arc<dyn StateStore>) -> Self {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        Self {
+            store,
+            process: format!(
+                "{}-{:x}-{}",
+                std::process::id(),
+                started.as_nanos(),
+                NEXT_THREADS_ID.fetch_add(1, Ordering::Relaxed)
+            ),
+        }
+    }
+
+    /// Record the `ts` of a message posted for a span, unless the span already has a thread.
+    pub(crate) fn record(&self, span_key: u64, response: &str) {
+        let ts = match serde_json::from_str::<Value>(response) {
+            Ok(Value::Object(response)) => match response.get("ts") {
+                Some(Value::String(ts)) => ts.clone(),
+                _ => return,
+            },
+            // Incoming webhooks only respond with `ok`, so messages are not threaded.
+            _ => return,
+        };
+        let key = self.key(span_key);
+        if self.store.get(&key).is_none() {
+            self.store.set(&key, ts);
+        }
+    }
+
+    /// The body of a message of a span, posted in the thread of the span if it has one.
+    pub(crate) fn in_thread(&self, span_key: u64, body: String) -> String {
+        let ts = match self.store.get(&self.key(span_key)) {
+            Some(ts) => ts,
+            None => return body,
+        };
+        match serde_json::from_str::<Value>(&body) {
+            Ok(Value::Object(mut message)) => {
+                message.insert("thread_ts".to_string(), Value::String(ts));
+                Value::Object(message).to_string()
+            }
+            _ => body,
+        }
+    }
+
+    fn key(&self, span_key: u64) -> String {
+        format!("slack-thread:{}:{:x}", self.process, span_key)
+    }
+}
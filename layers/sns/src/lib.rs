@@ -0,0 +1,291 @@
+#![doc = include_str!("../README.md")]
+
+use std::sync::Arc;
+
+use serde::Serialize;
+pub use tracing_layer_core::filters::EventFilters;
+pub use tracing_layer_core::layer::WebhookLayer;
+use tracing_layer_core::layer::WebhookLayerBuilder;
+pub use tracing_layer_core::BackgroundWorker;
+use tracing::Level;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use tracing_layer_core::Identity;
+pub use tracing_layer_core::clock::{Clock, MockClock, SystemClock};
+pub use tracing_layer_core::BuildError;
+use tracing_layer_core::{Config, RequestBuilder, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+use crate::sigv4::Credentials;
+
+mod sigv4;
+
+/// Layer for publishing tracing events to an AWS SNS topic.
+pub struct SnsLayer;
+
+impl SnsLayer {
+    pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<SnsConfig, Self> {
+        WebhookLayer::builder(app_name, target_filters)
+    }
+}
+
+impl SnsLayer {
+    /// The payload of the message for a single event.
+    fn payload(inputs: WebhookMessageInputs) -> SnsMessagePayload {
+        let caused_by: String = inputs
+            .error_sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| format!("\n    {}: {}", i, source))
+            .collect();
+        let message = format!(
+            concat!(
+                "Trace from {}\n",
+                "Event [{}]: \"{}\"{}\n",
+                "Target: {}\n",
+                "Span: {}\n",
+                "Metadata:\n",
+                "{}\n",
+                "Source: {}#L{}",
+            ),
+            inputs.app_name,
+            inputs.event_level.as_str(),
+            inputs.message,
+            caused_by,
+            inputs.target,
+            inputs.span,
+            inputs.metadata,
+            inputs.source_file,
+            inputs.source_line,
+        );
+        let message = match inputs.sequence {
+            Some(sequence) => format!("{}\nSequence: #{}", message, sequence),
+            None => message,
+        };
+        // Subjects must be printable ASCII on a single line, and at most 100 characters long.
+        let subject = format!("{} - {}", inputs.app_name, inputs.event_level)
+            .chars()
+            .filter(|c| c.is_ascii_graphic() || *c == ' ')
+            .take(100)
+            .collect();
+        SnsMessagePayload {
+            message,
+            subject,
+            webhook_url: inputs.webhook_url,
+            level: inputs.event_level,
+        }
+    }
+}
+
+impl WebhookMessageFactory for SnsLayer {
+    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+        Self::payload(inputs)
+    }
+
+    /// Combine the events into a single notification, with their messages separated by a blank line
+    /// and the subject of the most severe event.
+    fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage {
+        let payloads: Vec<_> = inputs.into_iter().map(Self::payload).collect();
+        // Levels compare as more verbose being greater, so ERROR is the lowest.
+        let most_severe = payloads.iter().min_by_key(|payload| payload.level).expect("batches are not empty");
+        SnsMessagePayload {
+            message: payloads.iter().map(|payload| payload.message.as_str()).collect::<Vec<_>>().join("\n\n"),
+            subject: most_severe.subject.clone(),
+            webhook_url: most_severe.webhook_url.clone(),
+            level: most_severe.level,
+        }
+    }
+}
+
+/// The parameters of the `Publish` action sent to SNS, besides the ones in the webhook URL.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct SnsMessagePayload {
+    #[serde(rename = "Message")]
+    message: String,
+    #[serde(rename = "Subject")]
+    subject: String,
+    #[serde(skip_serializing)]
+    webhook_url: String,
+    #[serde(skip_serializing)]
+    level: Level,
+}
+
+impl WebhookMessage for SnsMessagePayload {
+    fn webhook_url(&self) -> &str {
+        self.webhook_url.as_str()
+    }
+
+    fn serialize(&self) -> String {
+        serde_urlencoded::to_string(self).expect("failed to serialize sns message")
+    }
+
+    fn content_type(&self) -> &str {
+        "application/x-www-form-urlencoded"
+    }
+
+    fn level(&self) -> Option<Level> {
+        Some(self.level)
+    }
+
+    fn append_text(&mut self, text: &str) {
+        self.message.push('\n');
+        self.message.push_str(text);
+    }
+}
+
+/// Configuration describing how to publish tracing events to an AWS SNS topic.
+///
+/// The topic is addressed by the query of the webhook URL, so messages are published to it with
+/// the SNS HTTP API, and every request is signed with AWS Signature Version 4.
+pub struct SnsConfig {
+    pub(crate) region: String,
+    pub(crate) credentials: Credentials,
+    pub(crate) webhook_url: String,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub(crate) tls_identity: Option<Identity>,
+    pub(crate) clock: Arc<dyn Clock>,
+}
+
+impl SnsConfig {
+    pub fn new(region: String, topic_arn: String, access_key_id: String, secret_access_key: String) -> Self {
+        let query = serde_urlencoded::to_string([
+            ("Action", "Publish"),
+            ("TopicArn", topic_arn.as_str()),
+            ("Version", "2010-03-31"),
+        ])
+        .expect("failed to serialize sns query");
+        Self {
+            webhook_url: format!("https://sns.{}.amazonaws.com/?{}", region, query),
+            region,
+            credentials: Credentials {
+                access_key_id,
+                secret_access_key,
+                session_token: None,
+            },
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            tls_identity: None,
+            clock: Arc::new(SystemClock),
+        }
+    }
+
+    /// Sign requests with temporary credentials, e.g. those of an assumed role.
+    pub fn with_session_token(mut self, session_token: String) -> Self {
+        self.credentials.session_token = Some(session_token);
+        self
+    }
+
+    /// Present a client certificate to the endpoint, for endpoints behind a gateway that requires
+    /// mutual TLS.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn with_tls_identity(mut self, identity: Identity) -> Self {
+        self.tls_identity = Some(identity);
+        self
+    }
+
+    /// Use another source of the current time, e.g. a `MockClock` to test time-based features.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Create a new config for publishing messages to SNS using configuration available in the
+    /// environment.
+    ///
+    /// Required env vars:
+    ///   * SNS_TOPIC_ARN
+    ///   * AWS_REGION
+    ///   * AWS_ACCESS_KEY_ID
+    ///   * AWS_SECRET_ACCESS_KEY
+    ///
+    /// Optional env vars:
+    ///   * AWS_SESSION_TOKEN
+    pub fn new_from_env() -> Self {
+        Self::try_new_from_env().unwrap_or_else(|e| panic!("sns config in env: {}", e))
+    }
+
+    /// Like `new_from_env`, but returns an error naming the first missing variable rather than
+    /// panicking.
+    pub fn try_new_from_env() -> Result<Self, BuildError> {
+        let var = |var: &'static str| std::env::var(var).map_err(|_| BuildError::MissingEnvVar { var });
+        let config = Self::new(
+            var("AWS_REGION")?,
+            var("SNS_TOPIC_ARN")?,
+            var("AWS_ACCESS_KEY_ID")?,
+            var("AWS_SECRET_ACCESS_KEY")?,
+        );
+        Ok(match std::env::var("AWS_SESSION_TOKEN") {
+            Ok(session_token) => config.with_session_token(session_token),
+            Err(_) => config,
+        })
+    }
+}
+
+impl Default for SnsConfig {
+    fn default() -> Self {
+        Self::new_from_env()
+    }
+}
+
+impl Config for SnsConfig {
+    fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    fn tls_identity(&self) -> Option<Identity> {
+        self.tls_identity.clone()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    fn sign_request(&self, request: RequestBuilder, body: &[u8]) -> RequestBuilder {
+        sigv4::sign(request, body, "sns", &self.region, &self.credentials, self.clock.system_time())
+    }
+
+    fn new_from_env() -> Self
+    where
+        Self: Sized,
+    {
+        Self::new_from_env()
+    }
+
+    fn try_new_from_env() -> Result<Self, BuildError>
+    where
+        Self: Sized,
+    {
+        Self::try_new_from_env()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::sigv4::{sign, Credentials};
+
+    #[test]
+    fn requests_are_signed_like_the_aws_test_suite() {
+        // The `post-x-www-form-urlencoded` case of the AWS Signature Version 4 test suite.
+        let credentials = Credentials {
+            access_key_id: "AKIDEXAMPLE".to_string(),
+            secret_access_key: "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY".to_string(),
+            session_token: None,
+        };
+        let request = reqwest::Client::new()
+            .post("https://example.amazonaws.com/")
+            .header("Content-Type", "application/x-www-form-urlencoded");
+        let now = UNIX_EPOCH + Duration::from_secs(1440938160);
+        let request = sign(request, b"Param1=value1", "service", "us-east-1", &credentials, now)
+            .build()
+            .unwrap();
+        assert_eq!(request.headers()["x-amz-date"], "20150830T123600Z");
+        assert_eq!(
+            request.headers()["Authorization"],
+            concat!(
+                "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/service/aws4_request, ",
+                "SignedHeaders=content-type;host;x-amz-date, ",
+                "Signature=ff11897932ad3f4e8b18135d722051e5ac45fc38421b1da7b9d196a0fe09473a",
+            )
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use tracing_layer_core::RequestBuilder;
+
+/// The AWS credentials used to sign requests.
+#[derive(Clone)]
+pub(crate) struct Credentials {
+    pub(crate) access_key_id: String,
+    pub(crate) secret_access_key: String,
+    pub(crate) session_token: Option<String>,
+}
+
+/// Sign a request to an AWS service with Signature Version 4, adding the `Authorization`,
+/// `x-amz-date`, and (for temporary credentials) `x-amz-security-token` headers.
+///
+/// The request is returned unsigned if it cannot be inspected, e.g. because its URL is invalid, in
+/// which case sending it fails anyway.
+pub(crate) fn sign(
+    request: RequestBuilder,
+    body: &[u8],
+    service: &str,
+    region: &str,
+    credentials: &Credentials,
+    now: SystemTime,
+) -> RequestBuilder {
+    let built = match request.try_clone().map(RequestBuilder::build) {
+        Some(Ok(built)) => built,
+        _ => return request,
+    };
+    let url = built.url();
+    let host = match (url.host_str(), url.port()) {
+        (Some(host), Some(port)) => format!("{}:{}", host, port),
+        (Some(host), None) => host.to_string(),
+        (None, _) => return request,
+    };
+    let content_type = built
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    let amz_date = format_amz_date(now);
+    let date = &amz_date[..8];
+
+    let mut query: Vec<_> = url
+        .query_pairs()
+        .map(|(key, value)| format!("{}={}", uri_encode(&key), uri_encode(&value)))
+        .collect();
+    query.sort();
+
+    let mut headers = vec![
+        ("content-type", content_type.trim().to_string()),
+        ("host", host),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if let Some(token) = &credentials.session_token {
+        headers.push(("x-amz-security-token", token.clone()));
+    }
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = headers.iter().map(|(name, _)| *name).collect::<Vec<_>>().join(";");
+
+    let canonical_request = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        built.method().as_str(),
+        url.path(),
+        query.join("&"),
+        canonical_headers,
+        signed_headers,
+        hex(&Sha256::digest(body)),
+    );
+    let scope = format!("{}/{}/{}/aws4_request", date, region, service);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        hex(&Sha256::digest(canonical_request.as_bytes())),
+    );
+
+    let signing_key = [region, service, "aws4_request"].iter().fold(
+        hmac(format!("AWS4{}", credentials.secret_access_key).as_bytes(), date.as_bytes()),
+        |key, part| hmac(&key, part.as_bytes()),
+    );
+    let signature = hex(&hmac(&signing_key, string_to_sign.as_bytes()));
+    let authorization = format!(
+        "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+        credentials.access_key_id, scope, signed_headers, signature
+    );
+
+    let request = request
+        .header("x-amz-date", amz_date)
+        .header("Authorization", authorization);
+    match &credentials.session_token {
+        Some(token) => request.header("x-amz-security-token", token),
+        None => request,
+    }
+}
+
+fn hmac(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Percent-encode every byte except the unreserved characters, as required by the canonical query.
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => (byte as char).to_string(),
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+/// Format a time as `YYYYMMDD'T'HHMMSS'Z'`, in UTC.
+fn format_amz_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default();
+    let (days, secs_of_day) = ((secs / 86400) as i64, secs % 86400);
+    // Convert days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60
+    )
+}
@@ -0,0 +1,309 @@
+#![doc = include_str!("../README.md")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+pub use tracing_layer_core::filters::EventFilters;
+pub use tracing_layer_core::layer::WebhookLayer;
+use tracing_layer_core::layer::WebhookLayerBuilder;
+pub use tracing_layer_core::BackgroundWorker;
+use tracing::Level;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use tracing_layer_core::Identity;
+pub use tracing_layer_core::clock::{Clock, MockClock, SystemClock};
+pub use tracing_layer_core::BackoffJitter;
+pub use tracing_layer_core::BuildError;
+use tracing_layer_core::{
+    Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs, DEFAULT_MAX_RETRIES, DEFAULT_REQUEST_TIMEOUT,
+};
+
+/// Formats events as Microsoft Teams MessageCards: the target as the title, a theme color for the
+/// level of the event, and a section with the message and the fields of the event as facts.
+///
+/// Teams rejects messages above about 28 KB, which `WebhookLayerBuilder::max_payload_len` keeps
+/// verbose events under.
+pub struct TeamsMessageCardFactory;
+
+impl TeamsMessageCardFactory {
+    pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<TeamsConfig, Self> {
+        WebhookLayer::builder(app_name, target_filters)
+    }
+
+    /// The payload of a card with a section per event, titled with their target if they share it.
+    fn payload(inputs: Vec<WebhookMessageInputs>) -> TeamsMessagePayload {
+        // Levels compare as more verbose being greater, so ERROR is the lowest.
+        let level = inputs.iter().map(|inputs| inputs.event_level).min().unwrap_or(Level::ERROR);
+        let first = inputs.first().expect("messages have at least one event");
+        let title = if inputs.iter().all(|inputs| inputs.target == first.target) {
+            first.target.clone()
+        } else {
+            format!("{} events from {}", inputs.len(), first.app_name)
+        };
+        TeamsMessagePayload {
+            message_type: "MessageCard",
+            context: "https://schema.org/extensions",
+            theme_color: level_color(level),
+            // Shown in notifications, which do not render the card.
+            summary: format!("{} - {}: {}", first.app_name, first.event_level, first.message),
+            title,
+            webhook_url: first.webhook_url.clone(),
+            sections: inputs.into_iter().map(section).collect(),
+            level,
+        }
+    }
+}
+
+impl WebhookMessageFactory for TeamsMessageCardFactory {
+    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+        Self::payload(vec![inputs])
+    }
+
+    fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage {
+        Self::payload(inputs)
+    }
+}
+
+/// The theme color of a card for an event of the given level, as a hex code.
+fn level_color(level: Level) -> &'static str {
+    match level {
+        Level::TRACE | Level::DEBUG => "1ABC9C",
+        Level::INFO => "57F287",
+        Level::WARN => "E67E22",
+        Level::ERROR => "ED4245",
+    }
+}
+
+/// The section of a card describing a single event.
+fn section(inputs: WebhookMessageInputs) -> Value {
+    let mut facts = vec![(
+        "Source".to_string(),
+        format!("{}#L{}", inputs.source_file, inputs.source_line),
+    )];
+    if !inputs.span.is_empty() {
+        facts.push(("Span".to_string(), inputs.span));
+    }
+    if let Some(sequence) = inputs.sequence {
+        facts.push(("Sequence".to_string(), format!("#{}", sequence)));
+    }
+    if !inputs.error_sources.is_empty() {
+        let caused_by = inputs
+            .error_sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| format!("{}: {}", i, source))
+            .collect::<Vec<_>>()
+            .join("\n");
+        facts.push(("Caused by".to_string(), caused_by));
+    }
+    match serde_json::from_str::<Map<String, Value>>(&inputs.metadata) {
+        Ok(metadata) => facts.extend(metadata.into_iter().map(|(key, value)| match value {
+            Value::String(s) => (key, s),
+            value => (key, value.to_string()),
+        })),
+        Err(_) => facts.push(("Metadata".to_string(), inputs.metadata)),
+    }
+    json!({
+        "activityTitle": inputs.message,
+        "activitySubtitle": format!("{} - {}", inputs.app_name, inputs.event_level),
+        "facts": facts.into_iter().map(|(name, value)| json!({ "name": name, "value": value })).collect::<Vec<_>>(),
+        // Field values are sent as is, rather than rendered as Markdown.
+        "markdown": false,
+    })
+}
+
+/// The MessageCard sent to the incoming webhook of a Teams channel.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct TeamsMessagePayload {
+    #[serde(rename = "@type")]
+    message_type: &'static str,
+    #[serde(rename = "@context")]
+    context: &'static str,
+    theme_color: &'static str,
+    summary: String,
+    title: String,
+    sections: Vec<Value>,
+    #[serde(skip_serializing)]
+    webhook_url: String,
+    #[serde(skip_serializing)]
+    level: Level,
+}
+
+impl WebhookMessage for TeamsMessagePayload {
+    fn webhook_url(&self) -> &str {
+        self.webhook_url.as_str()
+    }
+
+    fn serialize(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize teams message")
+    }
+
+    fn level(&self) -> Option<Level> {
+        Some(self.level)
+    }
+
+    /// Appended as a section of the message.
+    fn append_text(&mut self, text: &str) {
+        self.sections.push(json!({ "text": text }));
+    }
+}
+
+/// Configuration describing how to forward tracing events to a Microsoft Teams channel.
+pub struct TeamsConfig {
+    pub(crate) webhook_url: String,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub(crate) tls_identity: Option<Identity>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) max_retries: usize,
+    pub(crate) request_timeout: Duration,
+    pub(crate) backoff_jitter: BackoffJitter,
+}
+
+impl TeamsConfig {
+    pub fn new(webhook_url: String) -> Self {
+        Self {
+            webhook_url,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            tls_identity: None,
+            clock: Arc::new(SystemClock),
+            max_retries: DEFAULT_MAX_RETRIES,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            backoff_jitter: BackoffJitter::default(),
+        }
+    }
+
+    /// Present a client certificate to the webhook endpoint, for endpoints behind a gateway that
+    /// requires mutual TLS.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn with_tls_identity(mut self, identity: Identity) -> Self {
+        self.tls_identity = Some(identity);
+        self
+    }
+
+    /// The number of attempts at delivering each message before giving up on it. Defaults to 10.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// How long to wait for the webhook to respond to a request before retrying it. Defaults to 30
+    /// seconds.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// How the delay between attempts at delivering a message is randomized. Defaults to
+    /// `BackoffJitter::None`.
+    pub fn with_backoff_jitter(mut self, backoff_jitter: BackoffJitter) -> Self {
+        self.backoff_jitter = backoff_jitter;
+        self
+    }
+
+    /// Use another source of the current time, e.g. a `MockClock` to test time-based features.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Create a new config for forwarding messages to Teams using configuration available in the
+    /// environment.
+    ///
+    /// Required env vars:
+    ///   * TEAMS_WEBHOOK_URL
+    pub fn new_from_env() -> Self {
+        Self::try_new_from_env().expect("teams webhook url in env")
+    }
+
+    /// Like `new_from_env`, but returns an error naming the missing variable rather than panicking.
+    pub fn try_new_from_env() -> Result<Self, BuildError> {
+        let webhook_url =
+            std::env::var("TEAMS_WEBHOOK_URL").map_err(|_| BuildError::MissingEnvVar { var: "TEAMS_WEBHOOK_URL" })?;
+        Ok(Self::new(webhook_url))
+    }
+}
+
+impl Default for TeamsConfig {
+    fn default() -> Self {
+        Self::new_from_env()
+    }
+}
+
+impl Config for TeamsConfig {
+    fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    fn tls_identity(&self) -> Option<Identity> {
+        self.tls_identity.clone()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    fn backoff_jitter(&self) -> BackoffJitter {
+        self.backoff_jitter
+    }
+
+    fn new_from_env() -> Self
+    where
+        Self: Sized,
+    {
+        Self::new_from_env()
+    }
+
+    fn try_new_from_env() -> Result<Self, BuildError>
+    where
+        Self: Sized,
+    {
+        Self::try_new_from_env()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tracing_layer_core::{testing, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+    use crate::TeamsMessageCardFactory;
+
+    fn inputs() -> WebhookMessageInputs {
+        WebhookMessageInputs {
+            webhook_url: "https://example.webhook.office.com/webhookb2/test".to_string(),
+            span: "create_user".to_string(),
+            metadata: json!({ "user_id": 7 }).to_string(),
+            ..testing::inputs()
+        }
+    }
+
+    #[test]
+    fn events_are_formatted_as_message_cards() {
+        let message = TeamsMessageCardFactory::create(inputs());
+        assert_eq!(message.webhook_url(), "https://example.webhook.office.com/webhookb2/test");
+        let card: Value = serde_json::from_str(&message.serialize()).unwrap();
+        assert_eq!(card["@type"], "MessageCard");
+        assert_eq!(card["themeColor"], "E67E22");
+        assert_eq!(card["title"], "app::users");
+        assert_eq!(card["sections"][0]["activityTitle"], "user created");
+        assert_eq!(
+            card["sections"][0]["facts"],
+            json!([
+                { "name": "Source", "value": "src/users.rs#L42" },
+                { "name": "Span", "value": "create_user" },
+                { "name": "user_id", "value": "7" }
+            ])
+        );
+    }
+}
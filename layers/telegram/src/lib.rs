@@ -0,0 +1,358 @@
+#![doc = include_str!("../README.md")]
+
+use std::sync::Arc;
+use std::time::Duration;
+
+use serde::Serialize;
+use serde_json::Value;
+pub use tracing_layer_core::filters::EventFilters;
+pub use tracing_layer_core::layer::WebhookLayer;
+use tracing_layer_core::layer::WebhookLayerBuilder;
+pub use tracing_layer_core::BackgroundWorker;
+use tracing::Level;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub use tracing_layer_core::Identity;
+pub use tracing_layer_core::clock::{Clock, MockClock, SystemClock};
+pub use tracing_layer_core::BackoffJitter;
+pub use tracing_layer_core::BuildError;
+use tracing_layer_core::{
+    Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs, DEFAULT_MAX_RETRIES, DEFAULT_REQUEST_TIMEOUT,
+};
+
+/// The maximum number of characters of the text of a message.
+const MAX_TEXT_CHARS: usize = 4096;
+const MAX_MESSAGE_CHARS: usize = 1000;
+const MAX_CAUSED_BY_CHARS: usize = 500;
+const MAX_METADATA_CHARS: usize = 2000;
+
+/// Formats events as Telegram messages in MarkdownV2: the level of the event and the app name, the
+/// message, the target and source location, and the fields of the event in a code block.
+///
+/// The chat the messages are sent to is added to their body by `TelegramConfig`. Telegram rejects
+/// messages above 4096 characters, so the events of a batch which do not fit in its message are only
+/// counted.
+pub struct TelegramFactory;
+
+impl TelegramFactory {
+    pub fn builder(app_name: String, target_filters: EventFilters) -> WebhookLayerBuilder<TelegramConfig, Self> {
+        WebhookLayer::builder(app_name, target_filters)
+    }
+
+    /// The message with the text of every event that fits, separated by blank lines.
+    fn payload(inputs: Vec<WebhookMessageInputs>) -> TelegramMessagePayload {
+        let first = inputs.first().expect("messages have at least one event");
+        let mut text = String::new();
+        for (i, event) in inputs.iter().map(event_text).enumerate() {
+            // Escaping only adds characters, so this overestimates the length Telegram checks.
+            let length = text.chars().count() + event.chars().count() + 2;
+            if i > 0 && length > MAX_TEXT_CHARS - 32 {
+                text.push_str(&format!("\n\n_\\+{} more events_", inputs.len() - i));
+                break;
+            }
+            if i > 0 {
+                text.push_str("\n\n");
+            }
+            text.push_str(&event);
+        }
+        TelegramMessagePayload {
+            text,
+            parse_mode: "MarkdownV2",
+            // Only silent if every event of the message is below the `notify_above` level.
+            disable_notification: inputs.iter().all(|inputs| !inputs.notify),
+            webhook_url: first.webhook_url.clone(),
+            // Levels compare as more verbose being greater, so ERROR is the lowest.
+            level: inputs.iter().map(|inputs| inputs.event_level).min().unwrap_or(Level::ERROR),
+        }
+    }
+}
+
+impl WebhookMessageFactory for TelegramFactory {
+    fn create(inputs: WebhookMessageInputs) -> impl WebhookMessage {
+        Self::payload(vec![inputs])
+    }
+
+    fn combine(inputs: Vec<WebhookMessageInputs>) -> impl WebhookMessage {
+        Self::payload(inputs)
+    }
+}
+
+/// The text describing a single event, in MarkdownV2.
+fn event_text(inputs: &WebhookMessageInputs) -> String {
+    let mut text = String::new();
+    if let Some(mention) = &inputs.presentation.mention {
+        text.push_str(&format!("{} ", escape(mention)));
+    }
+    if let Some(emoji) = &inputs.presentation.emoji {
+        text.push_str(&format!("{} ", escape(emoji)));
+    }
+    text.push_str(&format!("*{}* {}", inputs.event_level, escape(&inputs.app_name)));
+    if let Some(sequence) = inputs.sequence {
+        text.push_str(&format!(" \\#{}", sequence));
+    }
+    text.push_str(&format!("\n{}", escape(&truncate(&inputs.message, MAX_MESSAGE_CHARS))));
+    text.push_str(&format!(
+        "\n_{}_ at `{}#L{}`",
+        escape(&inputs.target),
+        escape_code(&inputs.source_file),
+        inputs.source_line
+    ));
+    if !inputs.span.is_empty() {
+        text.push_str(&format!("\nSpan: {}", escape(&inputs.span)));
+    }
+    if let Some(timestamp) = &inputs.timestamp {
+        text.push_str(&format!("\nTime: {}", escape(timestamp)));
+    }
+    if !inputs.error_sources.is_empty() {
+        let caused_by = inputs
+            .error_sources
+            .iter()
+            .enumerate()
+            .map(|(i, source)| format!("{}: {}", i, source))
+            .collect::<Vec<_>>()
+            .join("\n");
+        text.push_str(&format!(
+            "\n*Caused by:*\n```\n{}\n```",
+            escape_code(&truncate(&caused_by, MAX_CAUSED_BY_CHARS))
+        ));
+    }
+    text.push_str(&format!(
+        "\n```json\n{}\n```",
+        escape_code(&truncate(&inputs.metadata, MAX_METADATA_CHARS))
+    ));
+    text
+}
+
+/// Escape the characters with a meaning in MarkdownV2, so the text is shown as is.
+fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        if "_*[]()~`>#+-=|{}.!\\".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Escape the characters with a meaning in code and pre entities of MarkdownV2.
+fn escape_code(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('`', "\\`")
+}
+
+/// Truncate a text to at most `max` characters, ending it with an ellipsis if it is truncated.
+fn truncate(text: &str, max: usize) -> String {
+    if text.chars().count() <= max {
+        return text.to_string();
+    }
+    let mut truncated: String = text.chars().take(max - 1).collect();
+    truncated.push('…');
+    truncated
+}
+
+/// The body of a request to the `sendMessage` method of the Bot API, without its `chat_id`.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct TelegramMessagePayload {
+    text: String,
+    parse_mode: &'static str,
+    disable_notification: bool,
+    #[serde(skip_serializing)]
+    webhook_url: String,
+    #[serde(skip_serializing)]
+    level: Level,
+}
+
+impl WebhookMessage for TelegramMessagePayload {
+    fn webhook_url(&self) -> &str {
+        self.webhook_url.as_str()
+    }
+
+    fn serialize(&self) -> String {
+        serde_json::to_string(self).expect("failed to serialize telegram message")
+    }
+
+    fn level(&self) -> Option<Level> {
+        Some(self.level)
+    }
+
+    fn append_text(&mut self, text: &str) {
+        self.text.push('\n');
+        self.text.push_str(text);
+    }
+}
+
+/// Configuration describing how to forward tracing events to a Telegram chat.
+pub struct TelegramConfig {
+    pub(crate) webhook_url: String,
+    pub(crate) chat_id: String,
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub(crate) tls_identity: Option<Identity>,
+    pub(crate) clock: Arc<dyn Clock>,
+    pub(crate) max_retries: usize,
+    pub(crate) request_timeout: Duration,
+    pub(crate) backoff_jitter: BackoffJitter,
+}
+
+impl TelegramConfig {
+    /// Send messages with the bot of the given token to the given chat, e.g. `-1001234567890` for a
+    /// group, or the `@username` of a channel.
+    pub fn new(bot_token: String, chat_id: String) -> Self {
+        Self {
+            webhook_url: format!("https://api.telegram.org/bot{}/sendMessage", bot_token),
+            chat_id,
+            #[cfg(any(feature = "rustls", feature = "native-tls"))]
+            tls_identity: None,
+            clock: Arc::new(SystemClock),
+            max_retries: DEFAULT_MAX_RETRIES,
+            request_timeout: DEFAULT_REQUEST_TIMEOUT,
+            backoff_jitter: BackoffJitter::default(),
+        }
+    }
+
+    /// Present a client certificate to the Bot API, for a self-hosted server behind a gateway that
+    /// requires mutual TLS.
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    pub fn with_tls_identity(mut self, identity: Identity) -> Self {
+        self.tls_identity = Some(identity);
+        self
+    }
+
+    /// The number of attempts at delivering each message before giving up on it. Defaults to 10.
+    pub fn with_max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// How long to wait for the Bot API to respond to a request before retrying it. Defaults to 30
+    /// seconds.
+    pub fn with_request_timeout(mut self, request_timeout: Duration) -> Self {
+        self.request_timeout = request_timeout;
+        self
+    }
+
+    /// How the delay between attempts at delivering a message is randomized. Defaults to
+    /// `BackoffJitter::None`.
+    pub fn with_backoff_jitter(mut self, backoff_jitter: BackoffJitter) -> Self {
+        self.backoff_jitter = backoff_jitter;
+        self
+    }
+
+    /// Use another source of the current time, e.g. a `MockClock` to test time-based features.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Create a new config for forwarding messages to Telegram using configuration available in the
+    /// environment.
+    ///
+    /// Required env vars:
+    ///   * TELEGRAM_BOT_TOKEN
+    ///   * TELEGRAM_CHAT_ID
+    pub fn new_from_env() -> Self {
+        Self::try_new_from_env().unwrap_or_else(|e| panic!("telegram config in env: {}", e))
+    }
+
+    /// Like `new_from_env`, but returns an error naming the first missing variable rather than
+    /// panicking.
+    pub fn try_new_from_env() -> Result<Self, BuildError> {
+        let var = |var: &'static str| std::env::var(var).map_err(|_| BuildError::MissingEnvVar { var });
+        Ok(Self::new(var("TELEGRAM_BOT_TOKEN")?, var("TELEGRAM_CHAT_ID")?))
+    }
+}
+
+impl Default for TelegramConfig {
+    fn default() -> Self {
+        Self::new_from_env()
+    }
+}
+
+impl Config for TelegramConfig {
+    fn webhook_url(&self) -> &str {
+        &self.webhook_url
+    }
+
+    #[cfg(any(feature = "rustls", feature = "native-tls"))]
+    fn tls_identity(&self) -> Option<Identity> {
+        self.tls_identity.clone()
+    }
+
+    fn clock(&self) -> Arc<dyn Clock> {
+        self.clock.clone()
+    }
+
+    fn max_retries(&self) -> usize {
+        self.max_retries
+    }
+
+    fn request_timeout(&self) -> Duration {
+        self.request_timeout
+    }
+
+    fn backoff_jitter(&self) -> BackoffJitter {
+        self.backoff_jitter
+    }
+
+    /// Address the message to the chat of the config, which the factory does not know about.
+    fn prepare_body(&self, _message: &dyn WebhookMessage, body: String) -> String {
+        match serde_json::from_str::<Value>(&body) {
+            Ok(Value::Object(mut message)) => {
+                message.insert("chat_id".to_string(), Value::String(self.chat_id.clone()));
+                Value::Object(message).to_string()
+            }
+            _ => body,
+        }
+    }
+
+    fn new_from_env() -> Self
+    where
+        Self: Sized,
+    {
+        Self::new_from_env()
+    }
+
+    fn try_new_from_env() -> Result<Self, BuildError>
+    where
+        Self: Sized,
+    {
+        Self::try_new_from_env()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+    use tracing_layer_core::{testing, Config, WebhookMessage, WebhookMessageFactory, WebhookMessageInputs};
+
+    use crate::{escape, escape_code, TelegramConfig, TelegramFactory};
+
+    fn inputs() -> WebhookMessageInputs {
+        WebhookMessageInputs {
+            webhook_url: "https://api.telegram.org/bot123:ABC/sendMessage".to_string(),
+            metadata: json!({ "user_id": 7 }).to_string(),
+            ..testing::inputs()
+        }
+    }
+
+    #[test]
+    fn markdown_v2_special_characters_are_escaped() {
+        assert_eq!(escape("v1.2 (beta) - done!"), "v1\\.2 \\(beta\\) \\- done\\!");
+        assert_eq!(escape("snake_case *bold*"), "snake\\_case \\*bold\\*");
+        assert_eq!(escape_code("a`b\\c.d"), "a\\`b\\\\c.d");
+    }
+
+    #[test]
+    fn events_are_sent_to_the_chat_of_the_config() {
+        let config = TelegramConfig::new("123:ABC".to_string(), "-1001234567890".to_string());
+        assert_eq!(config.webhook_url(), "https://api.telegram.org/bot123:ABC/sendMessage");
+
+        let message = TelegramFactory::create(inputs());
+        let body: Value = serde_json::from_str(&config.prepare_body(&message, message.serialize())).unwrap();
+        assert_eq!(body["chat_id"], "-1001234567890");
+        assert_eq!(body["parse_mode"], "MarkdownV2");
+        assert_eq!(body["disable_notification"], false);
+        assert_eq!(
+            body["text"],
+            "*WARN* test\\-app\nuser created\n_app::users_ at `src/users.rs#L42`\n```json\n{\"user_id\":7}\n```"
+        );
+    }
+}